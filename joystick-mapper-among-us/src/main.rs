@@ -1,13 +1,12 @@
 use crate::vent::{select_vent, VentAction};
 use enigo::*;
 use joystick_mapper_lib::ActionClient;
-use joystick_mapper_lib::{Action, InputState, JoystickClient, MappingConfiguration};
+use joystick_mapper_lib::{Action, InputState, MapperError, RunOptions};
 use joystick_mapper_lib::{Key, MouseAction};
 use scrap::*;
 use serde::{Deserialize, Serialize};
-use serde_yaml;
 use std::io::ErrorKind::WouldBlock;
-use std::{env, fs, thread, time};
+use std::{process, thread, time};
 
 mod vent;
 
@@ -26,7 +25,7 @@ impl Action<Capturer> for VentAction {
         client: &mut ActionClient<Capturer>,
         input_state: InputState,
         _amount: Option<f32>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), MapperError> {
         if input_state == InputState::Up {
             return Ok(());
         }
@@ -39,7 +38,7 @@ impl Action<Capturer> for VentAction {
                         thread::sleep(time::Duration::new(1, 0) / 60);
                         continue;
                     } else {
-                        panic!("Error: {}", error);
+                        return Err(MapperError::Action(Box::new(error)));
                     }
                 }
             };
@@ -48,8 +47,8 @@ impl Action<Capturer> for VentAction {
             let max_vents = 3_u8;
             let vent = select_vent(&buffer, w as i32, h as i32, max_vents, self)?;
             if let Some(vent) = vent {
-                client.enigo.mouse_move_to(vent.0, vent.1);
-                client.enigo.mouse_click(MouseButton::Left);
+                client.output.mouse_move_to(vent.0, vent.1)?;
+                client.output.mouse_click(MouseButton::Left)?;
             }
             break;
         }
@@ -63,7 +62,7 @@ impl Action<Capturer> for KeyMouseVentAction {
         client: &mut ActionClient<Capturer>,
         input_state: InputState,
         amount: Option<f32>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), MapperError> {
         match self {
             KeyMouseVentAction::Key(key) => key.perform_action(client, input_state, amount),
             KeyMouseVentAction::Layout(c) => {
@@ -75,35 +74,16 @@ impl Action<Capturer> for KeyMouseVentAction {
     }
 }
 
-fn print_gamepads(client: &JoystickClient<KeyMouseVentAction, Capturer>) {
-    let gamepads = client.gamepads();
-    println!(
-        "Found {} joystick{}",
-        gamepads.len(),
-        if gamepads.len() == 1 { "" } else { "s" }
-    );
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("Error: {}", error);
+        process::exit(1);
+    }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let filename = env::args()
-        .nth(1)
-        .unwrap_or("joystick-mapper.conf".to_string());
-    let conf_content = fs::read_to_string(filename).expect("Failed reading the file");
-    let conf: MappingConfiguration<KeyMouseVentAction> = serde_yaml::from_str(&conf_content)?;
-    let display = Display::primary()?;
-    let capturer = Capturer::new(display)?;
-    let mut joystick_client: JoystickClient<KeyMouseVentAction, Capturer> =
-        JoystickClient::new(conf, capturer);
-    let pause = time::Duration::from_millis(15);
-    print_gamepads(&joystick_client);
-    let on_connected = || {
-        println!("New joystick connected!");
-    };
-    let on_disconnected = || {
-        println!("Joystick disconnected!");
-    };
-    loop {
-        joystick_client.exec_event_loop(Some(&on_connected), Some(&on_disconnected))?;
-        thread::sleep(pause);
-    }
+fn run() -> Result<(), MapperError> {
+    let setup_error = |error| MapperError::Setup(Box::new(error));
+    let display = Display::primary().map_err(setup_error)?;
+    let capturer = Capturer::new(display).map_err(setup_error)?;
+    joystick_mapper_lib::run::<KeyMouseVentAction, Capturer>(capturer, RunOptions::default())
 }
@@ -1,5 +1,9 @@
-use enigo::*;
+use crate::error::MapperError;
+use crate::output::OutputBackend;
+
+use enigo::{Enigo, Key, MouseButton};
 use fxhash::FxHashMap;
+use gilrs::{Axis, Button, GamepadId};
 use serde::{Deserialize, Serialize};
 
 pub trait Action<S> {
@@ -8,7 +12,7 @@ pub trait Action<S> {
         client: &mut ActionClient<S>,
         input_state: InputState,
         amount: Option<f32>,
-    ) -> Result<(), Box<dyn std::error::Error>>;
+    ) -> Result<(), MapperError>;
 }
 
 #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
@@ -22,6 +26,8 @@ pub enum MouseAction {
     MouseScrollDown,
     MouseScrollLeft,
     MouseScrollRight,
+    MouseScrollX,
+    MouseScrollY,
 }
 
 impl<S> Action<S> for MouseAction {
@@ -30,11 +36,12 @@ impl<S> Action<S> for MouseAction {
         client: &mut ActionClient<S>,
         input_state: InputState,
         amount: Option<f32>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), MapperError> {
+        let amount_from_axis = amount.is_some();
         let amount = amount.unwrap_or(1.0);
         let button_press = match &self {
-            MouseAction::MouseX => { client.mouse_axis_state[0] = amount; None }
-            MouseAction::MouseY => { client.mouse_axis_state[1] = amount; None }
+            MouseAction::MouseX => { client.slot_mut().mouse_axis_state[0] = amount; None }
+            MouseAction::MouseY => { client.slot_mut().mouse_axis_state[1] = amount; None }
             MouseAction::MouseLeft => Some(MouseButton::Left),
             MouseAction::MouseMiddle => Some(MouseButton::Middle),
             MouseAction::MouseRight => Some(MouseButton::Right),
@@ -42,11 +49,16 @@ impl<S> Action<S> for MouseAction {
             MouseAction::MouseScrollDown => Some(MouseButton::ScrollDown),
             MouseAction::MouseScrollLeft => Some(MouseButton::ScrollLeft),
             MouseAction::MouseScrollRight => Some(MouseButton::ScrollRight),
+            MouseAction::MouseScrollX => { client.slot_mut().scroll_axis_state[0] = amount; None }
+            MouseAction::MouseScrollY => { client.slot_mut().scroll_axis_state[1] = amount; None }
         };
         if let Some(mouse_button) = button_press {
+            if amount_from_axis && !client.axis_mouse_state_changed(*self, input_state) {
+                return Ok(());
+            }
             match input_state {
-                InputState::Up => client.enigo.mouse_up(mouse_button),
-                InputState::Down => client.enigo.mouse_down(mouse_button),
+                InputState::Up => client.mouse_up(mouse_button)?,
+                InputState::Down => client.mouse_down(mouse_button)?,
             }
         }
         Ok(())
@@ -59,24 +71,21 @@ impl<S> Action<S> for Key {
         client: &mut ActionClient<S>,
         input_state: InputState,
         amount: Option<f32>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), MapperError> {
         if let Some(_amount) = amount {
-            let action_state = client.axis_key_state.get_mut(self);
-            if let Some(action_state) = action_state {
-                if *action_state == input_state {
-                    return Ok(());
-                } else {
-                    *action_state = input_state;
-                }
-            } else {
-                client.axis_key_state.insert(*self, input_state);
+            let source = client.axis_source;
+            let previous_state = client
+                .slot_mut()
+                .axis_key_state
+                .insert((source, *self), input_state);
+            if previous_state.unwrap_or(InputState::Up) == input_state {
+                return Ok(());
             }
         }
         match input_state {
-            InputState::Up => client.enigo.key_up(*self),
-            InputState::Down => client.enigo.key_down(*self),
-        };
-        Ok(())
+            InputState::Up => client.key_up(*self),
+            InputState::Down => client.key_down(*self),
+        }
     }
 }
 
@@ -85,43 +94,257 @@ pub enum InputState {
     Up,
     Down,
 }
+
+/// Axis, or analog button, an action is performed for. Each one holds its
+/// actions down on its own, so another stick bound to the same key doesn't
+/// release it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AxisSource {
+    Axis {
+        gamepad: GamepadId,
+        axis: Axis,
+        code: Option<u32>,
+        positive: bool,
+    },
+    Button {
+        gamepad: GamepadId,
+        button: Button,
+        code: Option<u32>,
+    },
+}
+
 pub struct ActionClient<S> {
-    pub enigo: Enigo,
+    pub output: Box<dyn OutputBackend>,
     pub state: S,
-    axis_key_state: FxHashMap<Key, InputState>,
-    mouse_axis_state: [f32; 2],
+    axis_source: Option<AxisSource>,
+    // each gamepad drives its own slot, actions are performed on the selected one
+    slots: Vec<SlotState>,
+    slot: usize,
     mouse_speed: f32,
+    scroll_speed: f32,
 }
 
-impl<S> ActionClient<S> {
-    pub fn new(state: S, mouse_speed: f32) -> Self {
-        let enigo = Enigo::new();
-        let axis_key_state = FxHashMap::default();
+#[derive(Default)]
+struct SlotState {
+    axis_key_state: FxHashMap<(Option<AxisSource>, Key), InputState>,
+    axis_mouse_state: FxHashMap<(Option<AxisSource>, MouseAction), InputState>,
+    // every source holding a key or button down counts, it's released by the last one
+    held_keys: FxHashMap<Key, usize>,
+    held_mouse_buttons: Vec<(MouseButton, usize)>,
+    mouse_axis_state: [f32; 2],
+    scroll_axis_state: [f32; 2],
+    scroll_remainder: [f32; 2],
+}
+
+impl SlotState {
+    fn mouse_button_count(&mut self, mouse_button: MouseButton) -> Option<&mut usize> {
+        self.held_mouse_buttons
+            .iter_mut()
+            .find(|(held_button, _)| *held_button == mouse_button)
+            .map(|(_, count)| count)
+    }
+}
 
+impl<S> ActionClient<S> {
+    pub fn new(state: S, mouse_speed: f32, scroll_speed: f32) -> Self {
         ActionClient {
-            enigo,
+            output: Box::new(Enigo::new()),
             state,
-            axis_key_state,
-            mouse_axis_state: [0_f32, 0_f32],
+            axis_source: None,
+            slots: vec![SlotState::default()],
+            slot: 0,
             mouse_speed,
+            scroll_speed,
         }
     }
 
+    pub fn set_speeds(&mut self, mouse_speed: f32, scroll_speed: f32) {
+        self.mouse_speed = mouse_speed;
+        self.scroll_speed = scroll_speed;
+    }
+
     pub fn perform_action<A: Action<S>>(
         &mut self,
         action: &A,
         input_state: InputState,
         amount: Option<f32>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), MapperError> {
         action.perform_action(self, input_state, amount)
     }
-    pub fn exec_mouse_loop(&mut self) {
-        let [x_amount, y_amount] = self.mouse_axis_state;
-        if x_amount != 0_f32 && y_amount != 0_f32 {
-            self.enigo.mouse_move_relative(
-                (x_amount * self.mouse_speed.round()) as i32,
-                (y_amount * -self.mouse_speed.round()) as i32,
-            );
+
+    /// Performs an action for the amount of an axis, keeping what it holds
+    /// down apart from the other axis
+    pub fn perform_axis_action<A: Action<S>>(
+        &mut self,
+        action: &A,
+        source: AxisSource,
+        input_state: InputState,
+        amount: f32,
+    ) -> Result<(), MapperError> {
+        self.axis_source = Some(source);
+        let result = action.perform_action(self, input_state, Some(amount));
+        self.axis_source = None;
+        result
+    }
+
+    /// Selects the slot keeping track of what the following actions hold down
+    pub fn select_slot(&mut self, slot: usize) {
+        if slot >= self.slots.len() {
+            self.slots.resize_with(slot + 1, SlotState::default);
         }
+        self.slot = slot;
+    }
+
+    fn slot_mut(&mut self) -> &mut SlotState {
+        &mut self.slots[self.slot]
+    }
+
+    fn key_count(&self, key: Key) -> usize {
+        self.slots
+            .iter()
+            .filter_map(|slot| slot.held_keys.get(&key))
+            .sum()
+    }
+
+    fn mouse_button_count(&self, mouse_button: MouseButton) -> usize {
+        self.slots
+            .iter()
+            .flat_map(|slot| slot.held_mouse_buttons.iter())
+            .filter(|(held_button, _)| *held_button == mouse_button)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    pub fn key_down(&mut self, key: Key) -> Result<(), MapperError> {
+        *self.slot_mut().held_keys.entry(key).or_insert(0) += 1;
+        if self.key_count(key) == 1 {
+            self.output.key_down(key)?;
+        }
+        Ok(())
+    }
+
+    pub fn key_up(&mut self, key: Key) -> Result<(), MapperError> {
+        let slot = self.slot_mut();
+        if let Some(count) = slot.held_keys.get_mut(&key) {
+            *count -= 1;
+            if *count == 0 {
+                slot.held_keys.remove(&key);
+            }
+            if self.key_count(key) == 0 {
+                self.output.key_up(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn mouse_down(&mut self, mouse_button: MouseButton) -> Result<(), MapperError> {
+        let slot = self.slot_mut();
+        match slot.mouse_button_count(mouse_button) {
+            Some(count) => *count += 1,
+            None => slot.held_mouse_buttons.push((mouse_button, 1)),
+        }
+        if self.mouse_button_count(mouse_button) == 1 {
+            self.output.mouse_down(mouse_button)?;
+        }
+        Ok(())
+    }
+
+    pub fn mouse_up(&mut self, mouse_button: MouseButton) -> Result<(), MapperError> {
+        let slot = self.slot_mut();
+        if let Some(count) = slot.mouse_button_count(mouse_button) {
+            *count -= 1;
+            slot.held_mouse_buttons.retain(|(_, count)| *count > 0);
+            if self.mouse_button_count(mouse_button) == 0 {
+                self.output.mouse_up(mouse_button)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Releases every key and mouse button held down by a slot and stops its mouse,
+    /// returning the first failure after trying them all
+    pub fn release_slot(&mut self, slot: usize) -> Result<(), MapperError> {
+        if slot >= self.slots.len() {
+            return Ok(());
+        }
+        let released = std::mem::take(&mut self.slots[slot]);
+        let mut result = Ok(());
+        for (key, _) in released.held_keys {
+            if self.key_count(key) == 0 {
+                result = result.and(self.output.key_up(key));
+            }
+        }
+        for (mouse_button, _) in released.held_mouse_buttons {
+            if self.mouse_button_count(mouse_button) == 0 {
+                result = result.and(self.output.mouse_up(mouse_button));
+            }
+        }
+        result
+    }
+
+    /// Releases every key and mouse button held down and stops the mouse
+    pub fn release_all(&mut self) -> Result<(), MapperError> {
+        let mut result = Ok(());
+        for slot in 0..self.slots.len() {
+            result = result.and(self.release_slot(slot));
+        }
+        result
+    }
+
+    // axis events repeat the same state while the stick moves, only changes count
+    fn axis_mouse_state_changed(&mut self, action: MouseAction, input_state: InputState) -> bool {
+        let source = self.axis_source;
+        let previous_state = self
+            .slot_mut()
+            .axis_mouse_state
+            .insert((source, action), input_state);
+        previous_state.unwrap_or(InputState::Up) != input_state
+    }
+
+    pub fn tap_action<A: Action<S>>(
+        &mut self,
+        action: &A,
+    ) -> Result<(), MapperError> {
+        action.perform_action(self, InputState::Down, None)?;
+        action.perform_action(self, InputState::Up, None)
+    }
+
+    /// Whether a stick is moving the mouse or scrolling
+    pub fn is_mouse_moving(&self) -> bool {
+        self.slots
+            .iter()
+            .any(|slot| slot.mouse_axis_state != [0_f32; 2] || slot.scroll_axis_state != [0_f32; 2])
+    }
+
+    pub fn exec_mouse_loop(&mut self) -> Result<(), MapperError> {
+        for slot in self.slots.iter_mut() {
+            let [x_amount, y_amount] = slot.mouse_axis_state;
+            // either axis moves the mouse on its own, an analog trigger bound
+            // to `MouseX` never sets the other one
+            if x_amount != 0_f32 || y_amount != 0_f32 {
+                self.output.mouse_move_relative(
+                    (x_amount * self.mouse_speed.round()) as i32,
+                    (y_amount * -self.mouse_speed.round()) as i32,
+                )?;
+            }
+            // scrolling happens in whole steps, keep the fraction for the next loop
+            for axis in 0..2 {
+                let amount = slot.scroll_axis_state[axis];
+                if amount == 0_f32 {
+                    slot.scroll_remainder[axis] = 0_f32;
+                    continue;
+                }
+                let total = slot.scroll_remainder[axis] + amount * self.scroll_speed;
+                let steps = total.trunc();
+                slot.scroll_remainder[axis] = total - steps;
+                if steps != 0_f32 {
+                    match axis {
+                        0 => self.output.mouse_scroll_x(steps as i32),
+                        _ => self.output.mouse_scroll_y(-steps as i32),
+                    }?;
+                }
+            }
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,230 @@
+use crate::error::MapperError;
+
+use fxhash::FxHashMap;
+use gilrs::Axis;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::{fs, io};
+
+/// Axis calibrations of every calibrated gamepad, by gamepad uuid
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Calibration {
+    #[serde(default = "FxHashMap::default")]
+    pub gamepads: FxHashMap<String, FxHashMap<Axis, AxisCalibration>>,
+}
+
+/// Raw values of an axis at rest and at its ends, and the share of the
+/// range around the center ignored as noise
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct AxisCalibration {
+    pub center: f32,
+    pub min: f32,
+    pub max: f32,
+    #[serde(default)]
+    pub deadzone: f32,
+}
+
+/// Lowest, highest and average value of an axis seen while sampling
+#[derive(Debug, Clone, Copy)]
+pub struct AxisRange {
+    pub min: f32,
+    pub max: f32,
+    sum: f32,
+    count: usize,
+}
+
+/// Sampled axis ranges, by gamepad uuid and axis
+pub type AxisRanges = FxHashMap<(String, Axis), AxisRange>;
+
+impl Calibration {
+    /// Loads the calibration file, a missing file is an empty calibration
+    pub fn load(path: &str) -> Result<Calibration, MapperError> {
+        match fs::read_to_string(path) {
+            Ok(content) => {
+                serde_yaml::from_str(&content).map_err(|error| MapperError::yaml(path, error))
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Calibration::default()),
+            Err(error) => Err(MapperError::Io(path.to_string(), error)),
+        }
+    }
+
+    /// Saves the calibration file, creating its directory if needed
+    pub fn save(&self, path: &str) -> Result<(), MapperError> {
+        let content =
+            serde_yaml::to_string(self).map_err(|error| MapperError::yaml(path, error))?;
+        let io_error = |error| MapperError::Io(path.to_string(), error);
+        if let Some(directory) = Path::new(path).parent() {
+            fs::create_dir_all(directory).map_err(io_error)?;
+        }
+        fs::write(path, content).map_err(io_error)
+    }
+
+    pub fn axis(&self, uuid: &str, axis: Axis) -> Option<AxisCalibration> {
+        self.gamepads
+            .get(uuid)
+            .and_then(|axes| axes.get(&axis))
+            .copied()
+    }
+
+    /// Calibrates the axis sampled at rest in `idle` and moved through their
+    /// whole range in `full`, the deadzone is twice the noise at rest
+    pub fn update(&mut self, idle: &AxisRanges, full: &AxisRanges) {
+        for ((uuid, axis), range) in full {
+            let center = idle
+                .get(&(uuid.clone(), *axis))
+                .map_or(0_f32, AxisRange::mean);
+            let noise = idle
+                .get(&(uuid.clone(), *axis))
+                .map_or(0_f32, |idle| (idle.max - center).max(center - idle.min));
+            let min = range.min.min(center);
+            let max = range.max.max(center);
+            let half_range = (center - min).min(max - center);
+            let deadzone = if half_range > 0_f32 {
+                (noise * 2_f32 / half_range).min(0.5)
+            } else {
+                0_f32
+            };
+            self.gamepads.entry(uuid.clone()).or_default().insert(
+                *axis,
+                AxisCalibration {
+                    center,
+                    min,
+                    max,
+                    deadzone,
+                },
+            );
+        }
+    }
+}
+
+impl AxisCalibration {
+    /// Maps a raw value to -1.0..1.0 around the center, outside the deadzone
+    pub fn apply(&self, value: f32) -> f32 {
+        let offset = value - self.center;
+        let range = if offset < 0_f32 {
+            self.center - self.min
+        } else {
+            self.max - self.center
+        };
+        if range <= 0_f32 {
+            return 0_f32;
+        }
+        let normalized = (offset / range).clamp(-1_f32, 1_f32);
+        if normalized.abs() <= self.deadzone {
+            return 0_f32;
+        }
+        normalized.signum() * (normalized.abs() - self.deadzone) / (1_f32 - self.deadzone)
+    }
+}
+
+impl AxisRange {
+    pub fn new(value: f32) -> Self {
+        AxisRange {
+            min: value,
+            max: value,
+            sum: value,
+            count: 1,
+        }
+    }
+
+    pub fn add(&mut self, value: f32) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.count += 1;
+    }
+
+    pub fn mean(&self) -> f32 {
+        self.sum / self.count as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(value: f32, expected: f32) {
+        assert!(
+            (value - expected).abs() < 1e-4,
+            "{} is not {}",
+            value,
+            expected
+        );
+    }
+
+    fn ranges(uuid: &str, axis: Axis, values: &[f32]) -> AxisRanges {
+        let mut range = AxisRange::new(values[0]);
+        for value in &values[1..] {
+            range.add(*value);
+        }
+        let mut ranges = AxisRanges::default();
+        ranges.insert((uuid.to_string(), axis), range);
+        ranges
+    }
+
+    #[test]
+    fn apply_scales_each_side_of_the_center() {
+        let calibration = AxisCalibration {
+            center: 0.1,
+            min: -0.8,
+            max: 1.0,
+            deadzone: 0.0,
+        };
+        assert_close(calibration.apply(0.1), 0.0);
+        assert_close(calibration.apply(1.0), 1.0);
+        assert_close(calibration.apply(-0.8), -1.0);
+        assert_close(calibration.apply(-0.35), -0.5);
+        assert_close(calibration.apply(0.55), 0.5);
+        assert_close(calibration.apply(2.0), 1.0);
+    }
+
+    #[test]
+    fn apply_starts_from_zero_out_of_the_deadzone() {
+        let calibration = AxisCalibration {
+            center: 0.0,
+            min: -1.0,
+            max: 1.0,
+            deadzone: 0.2,
+        };
+        assert_close(calibration.apply(0.2), 0.0);
+        assert_close(calibration.apply(-0.1), 0.0);
+        assert_close(calibration.apply(0.6), 0.5);
+        assert_close(calibration.apply(-1.0), -1.0);
+    }
+
+    #[test]
+    fn apply_ignores_an_axis_without_range() {
+        let calibration = AxisCalibration {
+            center: 0.5,
+            min: 0.5,
+            max: 0.5,
+            deadzone: 0.0,
+        };
+        assert_close(calibration.apply(0.9), 0.0);
+    }
+
+    #[test]
+    fn update_centers_on_the_mean_at_rest() {
+        let idle = ranges("pad", Axis::LeftStickX, &[0.0, 0.02, -0.02]);
+        let full = ranges("pad", Axis::LeftStickX, &[-1.0, 0.0, 1.0]);
+        let mut calibration = Calibration::default();
+        calibration.update(&idle, &full);
+        let axis = calibration.axis("pad", Axis::LeftStickX).unwrap();
+        assert_close(axis.center, 0.0);
+        assert_close(axis.min, -1.0);
+        assert_close(axis.max, 1.0);
+        assert_close(axis.deadzone, 0.04);
+        assert!(calibration.axis("pad", Axis::LeftStickY).is_none());
+    }
+
+    #[test]
+    fn update_limits_the_deadzone() {
+        let idle = ranges("pad", Axis::RightZ, &[0.0, 0.3]);
+        let full = ranges("pad", Axis::RightZ, &[0.0, 0.6]);
+        let mut calibration = Calibration::default();
+        calibration.update(&idle, &full);
+        let axis = calibration.axis("pad", Axis::RightZ).unwrap();
+        assert_close(axis.center, 0.15);
+        assert_close(axis.deadzone, 0.5);
+    }
+}
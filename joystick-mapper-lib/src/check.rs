@@ -0,0 +1,1048 @@
+use crate::action_client::MouseAction;
+use crate::configuration_files::{aliases, merged_alone, resolve_aliases};
+use crate::configuration_format::ConfigurationFormat;
+use crate::error::{without_location, MapperError};
+use crate::mapping_configuration::{Input, MappingConfiguration, Repeat};
+
+use enigo::Key;
+use fxhash::FxHashMap;
+use gilrs::{Axis, Button};
+use serde::de::value::{Error as ValueError, StrDeserializer};
+use serde::de::{
+    Deserialize, DeserializeOwned, Deserializer, IntoDeserializer, MapAccess, SeqAccess, Visitor,
+};
+use serde_yaml::{Mapping, Value};
+use std::cmp::Ordering;
+use std::fmt;
+use std::marker::PhantomData;
+use toml::Spanned;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::{Marker, TScalarStyle};
+
+const CONFIGURATION_FIELDS: &[&str] = &[
+    "description",
+    "extends",
+    "include",
+    "actions",
+    "gamepads",
+    "virtual_gamepads",
+    "players",
+    "player_combo",
+    "dpad_axis_as_buttons",
+    "axis_sensitivity",
+    "mouse_speed",
+    "scroll_speed",
+    "controller_mappings",
+    "calibration_file",
+    "low_battery_threshold",
+    "battery_poll_interval",
+];
+const MAPPING_FIELDS: &[&str] = &["buttons", "axis", "analog"];
+const SELECTOR_FIELDS: &[&str] = &["uuid", "name", "index"];
+const VIRTUAL_GAMEPAD_FIELDS: &[&str] = &["parts"];
+const PART_FIELDS: &[&str] = &["buttons", "axis"];
+const DETAILED_BINDING_FIELDS: &[&str] = &["action", "on_press", "on_release", "repeat"];
+const REPEAT_FIELDS: &[&str] = &["delay", "rate"];
+const STAGE_FIELDS: &[&str] = &["threshold", "release_threshold", "action"];
+
+/// Problem found in a configuration, at a line and column starting from 1
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    /// The configuration can't be loaded
+    Error,
+    /// The configuration loads but likely doesn't do what is meant
+    Warning,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.line, self.column, severity, self.message
+        )
+    }
+}
+
+/// Checks a configuration with `A` as actions, reporting unknown fields,
+/// misspelled buttons, axis and actions, bindings given twice and actions
+/// held down by several inputs of a mapping, in order of appearance
+pub fn check_configuration<A: DeserializeOwned>(
+    content: &str,
+    format: ConfigurationFormat,
+) -> Vec<Diagnostic> {
+    let aliases = match format.parse("", content) {
+        Ok(configuration) => aliases(&configuration),
+        Err(_) => Mapping::new(),
+    };
+    check_with_aliases::<A>(content, format, &aliases)
+}
+
+/// Checks a configuration that can use the `actions` of the files it's merged with
+pub(crate) fn check_with_aliases<A: DeserializeOwned>(
+    content: &str,
+    format: ConfigurationFormat,
+    aliases: &Mapping,
+) -> Vec<Diagnostic> {
+    // JSON is also YAML, but not the other way around
+    if let Err(MapperError::Parse {
+        line,
+        column,
+        message,
+        ..
+    }) = format.parse("", content)
+    {
+        return vec![Diagnostic {
+            severity: Severity::Error,
+            line: line.unwrap_or(1),
+            column: column.unwrap_or(1),
+            message,
+        }];
+    }
+    let root = match format {
+        ConfigurationFormat::Toml => Node::parse_toml(content),
+        _ => Node::parse(content),
+    };
+    let root = match root {
+        Ok(root) => root,
+        Err(diagnostic) => return vec![diagnostic],
+    };
+    let mut checker = Checker::<A>::new(aliases);
+    checker.configuration(&root);
+    // whatever isn't checked above is left to the configuration parser
+    if !checker.has_errors() {
+        if let Err(error) = parse_merged::<A>(content, format, aliases) {
+            // YAML errors can tell where they are, when they aren't caused
+            // by something removed or named in the configuration
+            let located = match format {
+                ConfigurationFormat::Yaml => {
+                    serde_yaml::from_str::<MappingConfiguration<A>>(content)
+                        .err()
+                        .and_then(|error| {
+                            let location = error.location()?;
+                            Some((location.line(), location.column(), error.to_string()))
+                        })
+                }
+                _ => None,
+            };
+            let (line, column, message) = located.unwrap_or((1, 1, error));
+            checker.diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                line,
+                column,
+                message: without_location(message),
+            });
+        }
+    }
+    let mut diagnostics = checker.diagnostics;
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+    diagnostics
+}
+
+// YAML document keeping where each value starts, which serde_yaml values don't
+#[derive(Clone, Debug)]
+struct Node {
+    value: NodeValue,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Clone, Debug)]
+enum NodeValue {
+    /// Text and whether it's plain, quoted scalars are always strings
+    Scalar(String, bool),
+    Sequence(Vec<Node>),
+    Mapping(Vec<(Node, Node)>),
+}
+
+impl Node {
+    fn parse(content: &str) -> Result<Node, Diagnostic> {
+        let mut builder = NodeBuilder::default();
+        let result = Parser::new(content.chars()).load(&mut builder, false);
+        if let Err(error) = result {
+            return Err(Diagnostic {
+                severity: Severity::Error,
+                line: error.marker().line(),
+                column: error.marker().col() + 1,
+                message: without_location(error.to_string()),
+            });
+        }
+        builder.root.ok_or_else(|| Diagnostic {
+            severity: Severity::Error,
+            line: 1,
+            column: 1,
+            message: "the configuration is empty".to_string(),
+        })
+    }
+
+    fn parse_toml(content: &str) -> Result<Node, Diagnostic> {
+        match toml::from_str::<Spanned<TomlNode>>(content) {
+            Ok(root) => Ok(Node::from_toml(root, content, (1, 1))),
+            Err(error) => {
+                let (line, column) = error.line_col().unwrap_or_default();
+                Err(Diagnostic {
+                    severity: Severity::Error,
+                    line: line + 1,
+                    column: column + 1,
+                    message: without_location(error.to_string()),
+                })
+            }
+        }
+    }
+
+    // tables and arrays of tables have no span, they start where they're named
+    fn from_toml(node: Spanned<TomlNode>, content: &str, name_position: (usize, usize)) -> Node {
+        let (line, column) = match node.start() {
+            0 => name_position,
+            start => position(content, start),
+        };
+        let value = match node.into_inner() {
+            TomlNode::Scalar(text, plain) => NodeValue::Scalar(text, plain),
+            TomlNode::Array(items) => NodeValue::Sequence(
+                items
+                    .into_iter()
+                    .map(|item| Node::from_toml(item, content, (line, column)))
+                    .collect(),
+            ),
+            TomlNode::Table(entries) => NodeValue::Mapping(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let (line, column) = position(content, key.start());
+                        let key_node = Node {
+                            value: NodeValue::Scalar(key.into_inner(), false),
+                            line,
+                            column,
+                        };
+                        (key_node, Node::from_toml(value, content, (line, column)))
+                    })
+                    .collect(),
+            ),
+        };
+        Node {
+            value,
+            line,
+            column,
+        }
+    }
+
+    fn scalar(&self) -> Option<&str> {
+        match &self.value {
+            NodeValue::Scalar(text, _) => Some(text),
+            _ => None,
+        }
+    }
+
+    fn is_null(&self) -> bool {
+        matches!(&self.value, NodeValue::Scalar(text, true) if text.is_empty() || text == "~" || text == "null")
+    }
+
+    fn to_value(&self) -> Value {
+        match &self.value {
+            NodeValue::Scalar(text, true) => {
+                serde_yaml::from_str(text).unwrap_or_else(|_| Value::String(text.clone()))
+            }
+            NodeValue::Scalar(text, false) => Value::String(text.clone()),
+            NodeValue::Sequence(items) => {
+                Value::Sequence(items.iter().map(Node::to_value).collect())
+            }
+            NodeValue::Mapping(entries) => Value::Mapping(
+                entries
+                    .iter()
+                    .map(|(key, value)| (key.to_value(), value.to_value()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[derive(Default)]
+struct NodeBuilder {
+    stack: Vec<(Node, usize, Option<Node>)>,
+    anchors: FxHashMap<usize, Node>,
+    root: Option<Node>,
+}
+
+impl NodeBuilder {
+    fn add(&mut self, node: Node, anchor: usize) {
+        if anchor > 0 {
+            self.anchors.insert(anchor, node.clone());
+        }
+        match self.stack.last_mut() {
+            None => self.root = Some(node),
+            Some((parent, _, key)) => match &mut parent.value {
+                NodeValue::Sequence(items) => items.push(node),
+                NodeValue::Mapping(entries) => match key.take() {
+                    Some(key) => entries.push((key, node)),
+                    None => *key = Some(node),
+                },
+                NodeValue::Scalar(..) => {}
+            },
+        }
+    }
+}
+
+impl MarkedEventReceiver for NodeBuilder {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        let node = |value| Node {
+            value,
+            line: mark.line(),
+            column: mark.col() + 1,
+        };
+        match event {
+            Event::Scalar(text, style, anchor, _) => {
+                let plain = style == TScalarStyle::Plain;
+                self.add(node(NodeValue::Scalar(text, plain)), anchor);
+            }
+            Event::SequenceStart(anchor) => {
+                self.stack
+                    .push((node(NodeValue::Sequence(vec![])), anchor, None))
+            }
+            Event::MappingStart(anchor) => {
+                self.stack
+                    .push((node(NodeValue::Mapping(vec![])), anchor, None))
+            }
+            Event::SequenceEnd | Event::MappingEnd => {
+                if let Some((node, anchor, _)) = self.stack.pop() {
+                    self.add(node, anchor);
+                }
+            }
+            Event::Alias(anchor) => {
+                if let Some(node) = self.anchors.get(&anchor).cloned() {
+                    self.add(node, 0);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// TOML document with spans, strings are not plain scalars
+enum TomlNode {
+    Scalar(String, bool),
+    Array(Vec<Spanned<TomlNode>>),
+    Table(Vec<(Spanned<String>, Spanned<TomlNode>)>),
+}
+
+impl<'de> Deserialize<'de> for TomlNode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(TomlNodeVisitor)
+    }
+}
+
+struct TomlNodeVisitor;
+
+impl<'de> Visitor<'de> for TomlNodeVisitor {
+    type Value = TomlNode;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a TOML value")
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<TomlNode, E> {
+        Ok(TomlNode::Scalar(value.to_string(), true))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<TomlNode, E> {
+        Ok(TomlNode::Scalar(value.to_string(), true))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<TomlNode, E> {
+        Ok(TomlNode::Scalar(value.to_string(), true))
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<TomlNode, E> {
+        Ok(TomlNode::Scalar(value.to_string(), true))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<TomlNode, E> {
+        Ok(TomlNode::Scalar(value.to_string(), false))
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<TomlNode, S::Error> {
+        let mut items = vec![];
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(TomlNode::Array(items))
+    }
+
+    fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<TomlNode, M::Error> {
+        let mut entries = vec![];
+        while let Some(key) = map.next_key()? {
+            entries.push((key, map.next_value()?));
+        }
+        Ok(TomlNode::Table(entries))
+    }
+}
+
+// line and column starting from 1 of a byte offset
+fn position(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+struct Checker<A> {
+    diagnostics: Vec<Diagnostic>,
+    buttons: Vec<String>,
+    axis: Vec<String>,
+    actions: Vec<String>,
+    aliases: Vec<String>,
+    action: PhantomData<A>,
+}
+
+impl<A: DeserializeOwned> Checker<A> {
+    fn new(aliases: &Mapping) -> Self {
+        let mut actions = variant_names::<Key>();
+        // variants holding a value aren't written by name
+        actions.retain(|action| action != "Layout" && action != "Raw");
+        actions.extend(variant_names::<MouseAction>());
+        Checker {
+            diagnostics: vec![],
+            buttons: variant_names::<Button>(),
+            axis: variant_names::<Axis>(),
+            actions,
+            aliases: aliases
+                .iter()
+                .filter_map(|(name, _)| name.as_str().map(String::from))
+                .collect(),
+            action: PhantomData,
+        }
+    }
+
+    fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    fn report(&mut self, severity: Severity, node: &Node, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            line: node.line,
+            column: node.column,
+            message,
+        });
+    }
+
+    fn configuration(&mut self, root: &Node) {
+        let mut bound_actions = vec![];
+        for (name, value) in self.fields(root, &[CONFIGURATION_FIELDS, MAPPING_FIELDS]) {
+            match name {
+                "gamepads" => {
+                    for gamepad in self.items(value) {
+                        let mut bound_actions = vec![];
+                        for (name, value) in
+                            self.fields(gamepad, &[SELECTOR_FIELDS, MAPPING_FIELDS])
+                        {
+                            bound_actions.extend(self.mapping_field(name, value));
+                        }
+                        self.conflicts(&bound_actions);
+                    }
+                }
+                "virtual_gamepads" => {
+                    for virtual_gamepad in self.items(value) {
+                        let mut bound_actions = vec![];
+                        let fields =
+                            self.fields(virtual_gamepad, &[VIRTUAL_GAMEPAD_FIELDS, MAPPING_FIELDS]);
+                        for (name, value) in fields {
+                            if name == "parts" {
+                                for part in self.items(value) {
+                                    self.virtual_gamepad_part(part);
+                                }
+                            } else {
+                                bound_actions.extend(self.mapping_field(name, value));
+                            }
+                        }
+                        self.conflicts(&bound_actions);
+                    }
+                }
+                "players" => {
+                    for player in self.items(value) {
+                        let mut bound_actions = vec![];
+                        for (name, value) in self.fields(player, &[MAPPING_FIELDS]) {
+                            bound_actions.extend(self.mapping_field(name, value));
+                        }
+                        self.conflicts(&bound_actions);
+                    }
+                }
+                "actions" => {
+                    for (name, binding) in self.bindings(value) {
+                        if serde_yaml::from_value::<A>(name.to_value()).is_ok() {
+                            let message = format!(
+                                "`{}` is already an action, the alias replaces it in bindings",
+                                name.scalar().unwrap_or_default()
+                            );
+                            self.report(Severity::Warning, name, message);
+                        }
+                        self.binding(binding);
+                    }
+                }
+                "player_combo" => {
+                    for button in self.items(value) {
+                        self.input::<Button>(button, "button");
+                    }
+                }
+                _ => bound_actions.extend(self.mapping_field(name, value)),
+            }
+        }
+        self.conflicts(&bound_actions);
+    }
+
+    // returns the inputs of a mapping with the actions they hold down
+    fn mapping_field<'n>(&mut self, name: &str, value: &'n Node) -> Vec<(&'n Node, &'n Node)> {
+        let mut bound_actions = vec![];
+        match name {
+            "buttons" => {
+                for (input, binding) in self.bindings(value) {
+                    self.input::<Button>(input, "button");
+                    for action in self.binding(binding) {
+                        bound_actions.push((input, action));
+                    }
+                }
+            }
+            "axis" => {
+                for (input, actions) in self.bindings(value) {
+                    self.input::<Axis>(input, "axis");
+                    match &actions.value {
+                        NodeValue::Sequence(items) if items.len() == 2 => {
+                            for action in items {
+                                if self.action(action) {
+                                    bound_actions.push((input, action));
+                                }
+                            }
+                        }
+                        _ => self.report(
+                            Severity::Error,
+                            actions,
+                            "axis are bound to two actions, for the negative and positive directions"
+                                .to_string(),
+                        ),
+                    }
+                }
+            }
+            "analog" => {
+                for (input, binding) in self.bindings(value) {
+                    self.input::<Button>(input, "button");
+                    for action in self.analog_binding(binding) {
+                        bound_actions.push((input, action));
+                    }
+                }
+            }
+            _ => {}
+        }
+        bound_actions
+    }
+
+    // warns about actions held down by several inputs of the same mapping,
+    // whichever of buttons, axis and analog buttons they are
+    fn conflicts(&mut self, bound_actions: &[(&Node, &Node)]) {
+        let mut first_inputs: Vec<(&str, &str)> = vec![];
+        for (input, action) in bound_actions {
+            let (input, action_text) = match (input.scalar(), action.scalar()) {
+                (Some(input), Some(action_text)) => (input, action_text),
+                _ => continue,
+            };
+            let other_input = first_inputs
+                .iter()
+                .find(|(bound_action, _)| *bound_action == action_text)
+                .map(|(_, other_input)| *other_input);
+            match other_input {
+                Some(other_input) if other_input != input => self.report(
+                    Severity::Warning,
+                    action,
+                    format!(
+                        "`{}` is bound to both {} and {}",
+                        action_text, other_input, input
+                    ),
+                ),
+                Some(_) => {}
+                None => first_inputs.push((action_text, input)),
+            }
+        }
+    }
+
+    // returns the actions held down by the binding
+    fn binding<'n>(&mut self, node: &'n Node) -> Vec<&'n Node> {
+        if self.is_action(node) {
+            return vec![node];
+        }
+        match &node.value {
+            NodeValue::Sequence(items) => items.iter().filter(|item| self.action(item)).collect(),
+            NodeValue::Mapping(_) => {
+                let mut held_actions = vec![];
+                for (name, value) in self.fields(node, &[DETAILED_BINDING_FIELDS]) {
+                    match name {
+                        "repeat" => {
+                            for (name, field) in self.fields(value, &[REPEAT_FIELDS]) {
+                                self.repeat_field(name, field);
+                            }
+                        }
+                        _ => {
+                            let actions = match &value.value {
+                                NodeValue::Sequence(items) => items.iter().collect(),
+                                _ => vec![value],
+                            };
+                            for action in actions {
+                                if self.action(action) && name == "action" {
+                                    held_actions.push(action);
+                                }
+                            }
+                        }
+                    }
+                }
+                held_actions
+            }
+            NodeValue::Scalar(..) => {
+                self.action(node);
+                vec![]
+            }
+        }
+    }
+
+    // values the repeat can't be loaded with, like a rate too high to keep up with
+    fn repeat_field(&mut self, name: &str, node: &Node) {
+        let mut repeat = Mapping::new();
+        repeat.insert(Value::String(name.to_string()), node.to_value());
+        if let Err(error) = serde_yaml::from_value::<Repeat>(Value::Mapping(repeat)) {
+            self.report(Severity::Error, node, without_location(error.to_string()));
+        }
+    }
+
+    // returns the actions of the stages, or the action given the value
+    fn analog_binding<'n>(&mut self, node: &'n Node) -> Vec<&'n Node> {
+        match &node.value {
+            NodeValue::Sequence(stages) if !self.is_action(node) => {
+                let mut actions = vec![];
+                for stage in stages {
+                    for (name, value) in self.fields(stage, &[STAGE_FIELDS]) {
+                        if name == "action" && self.action(value) {
+                            actions.push(value);
+                        }
+                    }
+                }
+                actions
+            }
+            _ if self.action(node) => vec![node],
+            _ => vec![],
+        }
+    }
+
+    fn virtual_gamepad_part(&mut self, node: &Node) {
+        for (name, value) in self.fields(node, &[SELECTOR_FIELDS, PART_FIELDS]) {
+            match name {
+                "buttons" => {
+                    for input in self.items(value) {
+                        self.input::<Button>(input, "button");
+                    }
+                }
+                "axis" => {
+                    for input in self.items(value) {
+                        self.input::<Axis>(input, "axis");
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn is_alias(&self, node: &Node) -> bool {
+        matches!(node.scalar(), Some(name) if self.aliases.iter().any(|alias| alias == name))
+    }
+
+    fn is_action(&self, node: &Node) -> bool {
+        self.is_alias(node) || serde_yaml::from_value::<A>(node.to_value()).is_ok()
+    }
+
+    fn action(&mut self, node: &Node) -> bool {
+        if self.is_alias(node) {
+            return true;
+        }
+        match serde_yaml::from_value::<A>(node.to_value()) {
+            Ok(_) => true,
+            Err(error) => {
+                let message = match node.scalar() {
+                    Some(action) => {
+                        let names: Vec<String> =
+                            self.aliases.iter().chain(&self.actions).cloned().collect();
+                        format!(
+                            "unknown action `{}`{}",
+                            action,
+                            did_you_mean(action, &names)
+                        )
+                    }
+                    None => format!("invalid action: {}", without_location(error.to_string())),
+                };
+                self.report(Severity::Error, node, message);
+                false
+            }
+        }
+    }
+
+    fn input<T: DeserializeOwned>(&mut self, node: &Node, kind: &str) {
+        if let Err(error) = serde_yaml::from_value::<Input<T>>(node.to_value()) {
+            let names = if kind == "axis" {
+                &self.axis
+            } else {
+                &self.buttons
+            };
+            let message = match node.scalar() {
+                Some(input) if !input.starts_with("code:") => {
+                    format!("unknown {} `{}`{}", kind, input, did_you_mean(input, names))
+                }
+                _ => format!("invalid {}: {}", kind, without_location(error.to_string())),
+            };
+            self.report(Severity::Error, node, message);
+        }
+    }
+
+    fn items<'n>(&mut self, node: &'n Node) -> &'n [Node] {
+        match &node.value {
+            NodeValue::Sequence(items) => items,
+            _ if node.is_null() => &[],
+            _ => {
+                self.report(Severity::Error, node, "expected a list".to_string());
+                &[]
+            }
+        }
+    }
+
+    // bindings set to `~` remove the ones of an extended configuration
+    fn bindings<'n>(&mut self, node: &'n Node) -> Vec<(&'n Node, &'n Node)> {
+        let mut bindings = self.entries(node, "bound");
+        bindings.retain(|(_, binding)| !binding.is_null());
+        bindings
+    }
+
+    // entries of a mapping, warning about the keys given twice of which
+    // only the last one is loaded
+    fn entries<'n>(&mut self, node: &'n Node, repeated: &str) -> Vec<(&'n Node, &'n Node)> {
+        let entries = match &node.value {
+            NodeValue::Mapping(entries) => entries,
+            _ if node.is_null() => return vec![],
+            _ => {
+                self.report(Severity::Error, node, "expected a mapping".to_string());
+                return vec![];
+            }
+        };
+        for (index, (key, _)) in entries.iter().enumerate() {
+            let earlier = entries[..index].iter().find(|(earlier, _)| {
+                earlier.scalar().is_some() && earlier.scalar() == key.scalar()
+            });
+            if let Some((earlier, _)) = earlier {
+                let message = format!(
+                    "`{}` is {} twice, the one at line {} is ignored",
+                    key.scalar().unwrap_or_default(),
+                    repeated,
+                    earlier.line
+                );
+                self.report(Severity::Warning, key, message);
+            }
+        }
+        entries.iter().map(|(key, value)| (key, value)).collect()
+    }
+
+    // known fields of an object, reporting the unknown ones
+    fn fields<'n>(&mut self, node: &'n Node, known_fields: &[&[&str]]) -> Vec<(&'n str, &'n Node)> {
+        let known_names: Vec<String> = known_fields
+            .iter()
+            .flat_map(|fields| fields.iter().map(|field| field.to_string()))
+            .collect();
+        let mut fields = vec![];
+        for (key, value) in self.entries(node, "set") {
+            match key.scalar() {
+                Some(name) if known_names.iter().any(|known| known == name) => {
+                    fields.push((name, value))
+                }
+                name => {
+                    let name = name.unwrap_or_default();
+                    let message = format!(
+                        "unknown field `{}`{}",
+                        name,
+                        did_you_mean(name, &known_names)
+                    );
+                    self.report(Severity::Error, key, message);
+                }
+            }
+        }
+        fields
+    }
+}
+
+// without the bindings removed by `~` and with the aliases resolved, as when
+// the configuration is loaded
+fn parse_merged<A: DeserializeOwned>(
+    content: &str,
+    format: ConfigurationFormat,
+    aliases: &Mapping,
+) -> Result<(), String> {
+    let configuration = format
+        .parse("", content)
+        .map_err(|error| error.to_string())?;
+    let mut configuration = merged_alone(configuration);
+    resolve_aliases(&mut configuration, aliases)?;
+    serde_yaml::from_value::<MappingConfiguration<A>>(configuration)
+        .map(|_| ())
+        .map_err(|error| error.to_string())
+}
+
+// serde lists the variants of an enum when given an unknown one
+fn variant_names<T: DeserializeOwned>() -> Vec<String> {
+    let deserializer: StrDeserializer<ValueError> = "".into_deserializer();
+    match T::deserialize(deserializer) {
+        Ok(_) => vec![],
+        Err(error) => error
+            .to_string()
+            .split('`')
+            .skip(3)
+            .step_by(2)
+            .map(String::from)
+            .collect(),
+    }
+}
+
+fn did_you_mean(name: &str, candidates: &[String]) -> String {
+    let name = name.to_lowercase();
+    candidates
+        .iter()
+        .map(|candidate| {
+            (
+                strsim::jaro_winkler(&name, &candidate.to_lowercase()),
+                candidate,
+            )
+        })
+        .filter(|(similarity, _)| *similarity > 0.8)
+        .max_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+        .map(|(_, candidate)| format!(", did you mean `{}`?", candidate))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapping_configuration::{
+        AnalogStage, Binding, DetailedBinding, GamepadMapping, VirtualGamepad, VirtualGamepadPart,
+    };
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Deserialize, Serialize, Debug)]
+    #[serde(untagged)]
+    enum TestAction {
+        Key(Key),
+        Layout(char),
+        Mouse(MouseAction),
+    }
+
+    fn check(content: &str, format: ConfigurationFormat) -> Vec<String> {
+        check_configuration::<TestAction>(content, format)
+            .iter()
+            .map(Diagnostic::to_string)
+            .collect()
+    }
+
+    fn check_yaml(content: &str) -> Vec<String> {
+        check(content, ConfigurationFormat::Yaml)
+    }
+
+    // fields a struct is written with, as parsed from `content`
+    fn struct_fields<T: DeserializeOwned + Serialize>(content: &str) -> Vec<String> {
+        let value: T = serde_yaml::from_str(content).unwrap();
+        let mut fields: Vec<String> = match serde_yaml::to_value(&value).unwrap() {
+            Value::Mapping(mapping) => mapping
+                .iter()
+                .filter_map(|(key, _)| key.as_str().map(String::from))
+                .collect(),
+            _ => vec![],
+        };
+        fields.sort();
+        fields
+    }
+
+    fn field_list(lists: &[&[&str]]) -> Vec<String> {
+        let mut fields: Vec<String> = lists
+            .iter()
+            .flat_map(|list| list.iter().map(|field| field.to_string()))
+            .collect();
+        fields.sort();
+        fields
+    }
+
+    #[test]
+    fn misspellings_are_located_with_a_suggestion() {
+        assert_eq!(
+            check_yaml("buttons:\n  Nroth: Spce\nmouse_sped: 3\n"),
+            vec![
+                "2:3: error: unknown button `Nroth`, did you mean `North`?",
+                "2:10: error: unknown action `Spce`, did you mean `Space`?",
+                "3:1: error: unknown field `mouse_sped`, did you mean `mouse_speed`?",
+            ]
+        );
+        assert_eq!(
+            check_yaml("axis:\n  LeftStikX: [a, d]\n"),
+            vec!["2:3: error: unknown axis `LeftStikX`, did you mean `LeftStickX`?"]
+        );
+        assert_eq!(
+            check_yaml("buttons:\n  South: Xyzzy\n"),
+            vec!["2:10: error: unknown action `Xyzzy`"]
+        );
+    }
+
+    #[test]
+    fn json_and_toml_are_located() {
+        assert_eq!(
+            check(
+                "{\n  \"buttons\": {\"Nroth\": \"a\"}\n}",
+                ConfigurationFormat::Json
+            ),
+            vec!["2:15: error: unknown button `Nroth`, did you mean `North`?"]
+        );
+        assert_eq!(
+            check(
+                "mouse_speed = 3\n\n[buttons]\nSouth = \"Spce\"\n",
+                ConfigurationFormat::Toml
+            ),
+            vec!["4:9: error: unknown action `Spce`, did you mean `Space`?"]
+        );
+    }
+
+    #[test]
+    fn syntax_errors_are_located() {
+        assert_eq!(
+            check("mouse_speed = 3\nbuttons = [", ConfigurationFormat::Toml).len(),
+            1
+        );
+        let diagnostics =
+            check_configuration::<TestAction>("buttons:\n  South: [a\n", ConfigurationFormat::Yaml);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].line, 3);
+    }
+
+    #[test]
+    fn aliases_are_known_actions() {
+        assert!(check_yaml("actions:\n  jump: Space\nbuttons:\n  South: jump\n").is_empty());
+        assert_eq!(
+            check_yaml("actions:\n  jump: Space\nbuttons:\n  South: jmup\n"),
+            vec!["4:10: error: unknown action `jmup`, did you mean `jump`?"]
+        );
+        assert_eq!(
+            check_yaml("actions:\n  Space: Tab\n"),
+            vec!["2:3: warning: `Space` is already an action, the alias replaces it in bindings"]
+        );
+    }
+
+    #[test]
+    fn conflicts_span_buttons_axis_and_analog() {
+        assert_eq!(
+            check_yaml("buttons:\n  DPadUp: w\naxis:\n  LeftStickY: [s, w]\n"),
+            vec!["4:19: warning: `w` is bound to both DPadUp and LeftStickY"]
+        );
+        assert_eq!(
+            check_yaml(
+                "analog:\n  LeftTrigger2:\n    - threshold: 0.5\n      action: q\nbuttons:\n  West: q\n"
+            ),
+            vec!["6:9: warning: `q` is bound to both LeftTrigger2 and West"]
+        );
+        // held by one input only, or tapped
+        assert!(check_yaml("axis:\n  LeftStickX: [a, a]\n").is_empty());
+        assert!(check_yaml("buttons:\n  South: a\n  East:\n    on_press: a\n").is_empty());
+    }
+
+    #[test]
+    fn conflicts_are_within_a_mapping() {
+        let content = "buttons:\n  South: a\nplayers:\n  - buttons:\n      South: a\n  - axis:\n      LeftStickX: [a, d]\n";
+        assert!(check_yaml(content).is_empty());
+    }
+
+    #[test]
+    fn repeat_rates_out_of_range_are_errors() {
+        let repeat = |rate: &str| {
+            check_yaml(&format!(
+                "buttons:\n  South:\n    action: a\n    repeat:\n      rate: {}\n",
+                rate
+            ))
+        };
+        assert!(repeat("0.5").is_empty());
+        assert!(repeat("1000").is_empty());
+        for rate in &[".inf", ".nan", "0", "-5", "1e9"] {
+            let diagnostics = repeat(rate);
+            assert_eq!(diagnostics.len(), 1, "rate {}", rate);
+            assert!(
+                diagnostics[0].contains("error: invalid repeat rate"),
+                "{}",
+                diagnostics[0]
+            );
+        }
+    }
+
+    #[test]
+    fn keys_given_twice_are_warnings_as_the_last_one_is_loaded() {
+        let content = "buttons:\n  South: a\n  South: b\n";
+        assert_eq!(
+            check_yaml(content),
+            vec!["3:3: warning: `South` is bound twice, the one at line 2 is ignored"]
+        );
+        let configuration: MappingConfiguration<TestAction> = ConfigurationFormat::Yaml
+            .parse("", content)
+            .and_then(|value| {
+                serde_yaml::from_value(value).map_err(|error| MapperError::yaml("", error))
+            })
+            .unwrap();
+        let bindings: Vec<_> = configuration.mapping.buttons.values().collect();
+        assert!(matches!(
+            bindings[..],
+            [Binding::Action(TestAction::Layout('b'))]
+        ));
+    }
+
+    #[test]
+    fn field_lists_match_the_configuration() {
+        let configuration_fields: Vec<&str> = CONFIGURATION_FIELDS
+            .iter()
+            .copied()
+            // read while merging the files rather than deserialized
+            .filter(|field| !["extends", "include", "actions"].contains(field))
+            .collect();
+        assert_eq!(
+            struct_fields::<MappingConfiguration<TestAction>>("{}"),
+            field_list(&[&configuration_fields, MAPPING_FIELDS])
+        );
+        assert_eq!(
+            struct_fields::<GamepadMapping<TestAction>>("{}"),
+            field_list(&[SELECTOR_FIELDS, MAPPING_FIELDS])
+        );
+        assert_eq!(
+            struct_fields::<VirtualGamepad<TestAction>>("parts: []"),
+            field_list(&[VIRTUAL_GAMEPAD_FIELDS, MAPPING_FIELDS])
+        );
+        assert_eq!(
+            struct_fields::<VirtualGamepadPart>("{}"),
+            field_list(&[SELECTOR_FIELDS, PART_FIELDS])
+        );
+        assert_eq!(
+            struct_fields::<DetailedBinding<TestAction>>("{}"),
+            field_list(&[DETAILED_BINDING_FIELDS])
+        );
+        assert_eq!(struct_fields::<Repeat>("{}"), field_list(&[REPEAT_FIELDS]));
+        assert_eq!(
+            struct_fields::<AnalogStage<TestAction>>("{threshold: 0.5, action: a}"),
+            field_list(&[STAGE_FIELDS])
+        );
+    }
+}
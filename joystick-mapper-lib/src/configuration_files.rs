@@ -0,0 +1,463 @@
+use crate::configuration_format::ConfigurationFormat;
+use crate::error::MapperError;
+
+use serde_yaml::{Mapping, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A configuration file read along with the files it `extends` and `include`s,
+/// in the order they are merged: the base first, then the included files and
+/// last the file itself. Files are read in the format told by their extension,
+/// but for the first one which can be given another
+pub(crate) struct ConfigurationFiles {
+    pub files: Vec<ConfigurationFile>,
+}
+
+pub(crate) struct ConfigurationFile {
+    pub path: String,
+    pub content: String,
+    pub format: ConfigurationFormat,
+    value: Value,
+}
+
+impl ConfigurationFiles {
+    pub fn read(path: &str, format: ConfigurationFormat) -> Result<Self, MapperError> {
+        let mut files = ConfigurationFiles { files: vec![] };
+        files.visit(path, format, &mut vec![])?;
+        Ok(files)
+    }
+
+    /// Paths of the files, whatever can't be read is left out
+    pub fn paths(path: &str, format: ConfigurationFormat) -> Vec<String> {
+        match ConfigurationFiles::read(path, format) {
+            Ok(files) => files.files.into_iter().map(|file| file.path).collect(),
+            Err(_) => vec![path.to_string()],
+        }
+    }
+
+    /// Every file merged over the previous ones: mappings like `buttons`
+    /// are merged by key, anything else is replaced, then the names of
+    /// `actions` are replaced by what they stand for
+    pub fn merged(&self) -> Result<Value, MapperError> {
+        let mut merged = self.merged_files();
+        let aliases = aliases(&merged);
+        resolve_aliases(&mut merged, &aliases).map_err(|message| MapperError::Parse {
+            path: self.files.last().map(|file| file.path.clone()),
+            line: None,
+            column: None,
+            message,
+        })?;
+        Ok(merged)
+    }
+
+    /// The `actions` of every file
+    pub fn aliases(&self) -> Mapping {
+        aliases(&self.merged_files())
+    }
+
+    fn merged_files(&self) -> Value {
+        let mut merged = Value::Mapping(Mapping::new());
+        for file in &self.files {
+            merge(&mut merged, file.value.clone(), 0);
+        }
+        merged
+    }
+
+    fn visit(
+        &mut self,
+        path: &str,
+        format: ConfigurationFormat,
+        parents: &mut Vec<PathBuf>,
+    ) -> Result<(), MapperError> {
+        let io_error = |error| MapperError::Io(path.to_string(), error);
+        let canonical_path = fs::canonicalize(path).map_err(io_error)?;
+        if parents.contains(&canonical_path) {
+            return Err(MapperError::Parse {
+                path: Some(path.to_string()),
+                line: None,
+                column: None,
+                message: "the configuration extends or includes itself".to_string(),
+            });
+        }
+        let content = fs::read_to_string(path).map_err(io_error)?;
+        let mut value = match format.parse(path, &content)? {
+            Value::Null => Value::Mapping(Mapping::new()),
+            value => value,
+        };
+        let mut extended = vec![];
+        if let Value::Mapping(mapping) = &mut value {
+            resolve_paths(mapping, path);
+            for field in &["extends", "include"] {
+                match mapping.remove(&Value::from(*field)) {
+                    Some(Value::String(file)) => extended.push(file),
+                    Some(Value::Sequence(files)) => {
+                        for file in files {
+                            match file {
+                                Value::String(file) => extended.push(file),
+                                _ => return Err(invalid_field(path, field)),
+                            }
+                        }
+                    }
+                    Some(_) => return Err(invalid_field(path, field)),
+                    None => {}
+                }
+            }
+        }
+        parents.push(canonical_path);
+        for file in extended {
+            let file = relative_to(path, &file);
+            let format = ConfigurationFormat::from_path(&file);
+            self.visit(&file, format, parents)?;
+        }
+        parents.pop();
+        self.files.push(ConfigurationFile {
+            path: path.to_string(),
+            content,
+            format,
+            value,
+        });
+        Ok(())
+    }
+}
+
+/// A configuration as it is when merged over nothing
+pub(crate) fn merged_alone(value: Value) -> Value {
+    let mut merged = Value::Mapping(Mapping::new());
+    merge(&mut merged, value, 0);
+    merged
+}
+
+/// The `actions` of a configuration, by name
+pub(crate) fn aliases(configuration: &Value) -> Mapping {
+    match configuration.get("actions") {
+        Some(Value::Mapping(aliases)) => aliases.clone(),
+        _ => Mapping::new(),
+    }
+}
+
+/// Replaces the names of `aliases` wherever actions are bound, removing the
+/// `actions` field
+pub(crate) fn resolve_aliases(configuration: &mut Value, aliases: &Mapping) -> Result<(), String> {
+    let configuration = match configuration {
+        Value::Mapping(configuration) => configuration,
+        _ => return Ok(()),
+    };
+    configuration.remove(&Value::from("actions"));
+    resolve_mapping(configuration, aliases)?;
+    for field in &["gamepads", "virtual_gamepads", "players"] {
+        if let Some(Value::Sequence(mappings)) = configuration.get_mut(&Value::from(*field)) {
+            for mapping in mappings {
+                if let Value::Mapping(mapping) = mapping {
+                    resolve_mapping(mapping, aliases)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn resolve_mapping(mapping: &mut Mapping, aliases: &Mapping) -> Result<(), String> {
+    for binding in bindings(mapping, "buttons") {
+        resolve_actions(binding, aliases, 0)?;
+        if let Value::Mapping(binding) = binding {
+            for field in &["action", "on_press", "on_release"] {
+                if let Some(actions) = binding.get_mut(&Value::from(*field)) {
+                    resolve_actions(actions, aliases, 0)?;
+                }
+            }
+        }
+    }
+    for binding in bindings(mapping, "axis") {
+        if let Value::Sequence(actions) = binding {
+            for action in actions {
+                resolve_actions(action, aliases, 0)?;
+            }
+        }
+    }
+    for binding in bindings(mapping, "analog") {
+        match binding {
+            Value::Sequence(stages) if stages.iter().all(Value::is_mapping) => {
+                for stage in stages {
+                    if let Some(action) = stage.get_mut("action") {
+                        resolve_actions(action, aliases, 0)?;
+                    }
+                }
+            }
+            _ => resolve_actions(binding, aliases, 0)?,
+        }
+    }
+    Ok(())
+}
+
+fn bindings<'m>(mapping: &'m mut Mapping, field: &str) -> Vec<&'m mut Value> {
+    match mapping.get_mut(&Value::from(field)) {
+        Some(Value::Mapping(bindings)) => bindings.iter_mut().map(|(_, binding)| binding).collect(),
+        _ => vec![],
+    }
+}
+
+// an action or a list of them, aliases standing for a list are spliced in
+fn resolve_actions(actions: &mut Value, aliases: &Mapping, depth: usize) -> Result<(), String> {
+    if depth > aliases.len() {
+        return Err("`actions` refer to each other in a loop".to_string());
+    }
+    match actions {
+        Value::Sequence(items) => {
+            let mut resolved = vec![];
+            for mut item in items.drain(..) {
+                if aliases.contains_key(&item) {
+                    resolve_actions(&mut item, aliases, depth)?;
+                    if let Value::Sequence(items) = item {
+                        resolved.extend(items);
+                        continue;
+                    }
+                }
+                resolved.push(item);
+            }
+            *items = resolved;
+        }
+        Value::String(_) => {
+            if let Some(alias) = aliases.get(actions) {
+                *actions = alias.clone();
+                resolve_actions(actions, aliases, depth + 1)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// files named in a configuration are relative to it, like the ones it extends
+fn resolve_paths(configuration: &mut Mapping, path: &str) {
+    if let Some(Value::String(file)) = configuration.get_mut(&Value::from("calibration_file")) {
+        *file = relative_to(path, file);
+    }
+    if let Some(Value::Sequence(controller_mappings)) =
+        configuration.get_mut(&Value::from("controller_mappings"))
+    {
+        for controller_mapping in controller_mappings {
+            match controller_mapping {
+                // SDL mapping lines are comma separated, anything else is a file
+                Value::String(file) if !file.contains(',') => *file = relative_to(path, file),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn relative_to(path: &str, file: &str) -> String {
+    match Path::new(path).parent() {
+        Some(directory) => directory.join(file).to_string_lossy().into_owned(),
+        None => file.to_string(),
+    }
+}
+
+fn invalid_field(path: &str, field: &str) -> MapperError {
+    MapperError::Parse {
+        path: Some(path.to_string()),
+        line: None,
+        column: None,
+        message: format!("`{}` is a file or a list of files", field),
+    }
+}
+
+// top level fields and the entries of mappings like `buttons` are merged,
+// an entry set to `~` removes the inherited one
+fn merge(base: &mut Value, value: Value, depth: usize) {
+    match (base, value) {
+        (Value::Mapping(base), Value::Mapping(mapping)) if depth < 2 => {
+            for (key, value) in mapping {
+                if depth == 1 && value.is_null() {
+                    base.remove(&key);
+                } else if let Some(base_value) = base.get_mut(&key) {
+                    merge(base_value, value, depth + 1);
+                } else {
+                    let mut base_value = Value::Mapping(Mapping::new());
+                    merge(&mut base_value, value, depth + 1);
+                    base.insert(key, base_value);
+                }
+            }
+        }
+        (base, value) => *base = value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(content: &str) -> Value {
+        serde_yaml::from_str(content).unwrap()
+    }
+
+    // configuration files in a directory of their own
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("joystick-mapper-{}-{}", name, std::process::id()));
+        for (path, content) in files {
+            let path = directory.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        directory
+    }
+
+    fn read_merged(directory: &Path, path: &str) -> Result<Value, MapperError> {
+        let path = directory.join(path);
+        let path = path.to_str().unwrap();
+        ConfigurationFiles::read(path, ConfigurationFormat::from_path(path))?.merged()
+    }
+
+    fn resolved(content: &str) -> Result<Value, String> {
+        let mut configuration = yaml(content);
+        let aliases = aliases(&configuration);
+        resolve_aliases(&mut configuration, &aliases).map(|_| configuration)
+    }
+
+    #[test]
+    fn merge_merges_mappings_by_key() {
+        let mut base = yaml("mouse_speed: 10\nbuttons:\n  South: a\n  East: b\n");
+        let value = yaml("mouse_speed: 20\nbuttons:\n  East: c\n  North: d\n");
+        merge(&mut base, value, 0);
+        assert_eq!(
+            base,
+            yaml("mouse_speed: 20\nbuttons:\n  South: a\n  East: c\n  North: d\n")
+        );
+    }
+
+    #[test]
+    fn merge_removes_entries_set_to_null() {
+        let mut base = yaml("buttons:\n  South: a\n  East: b\n");
+        merge(&mut base, yaml("buttons:\n  East: ~\n"), 0);
+        assert_eq!(base, yaml("buttons:\n  South: a\n"));
+    }
+
+    #[test]
+    fn merge_replaces_bindings_and_lists() {
+        let mut base = yaml("buttons:\n  South:\n    action: a\n    on_press: b\nplayers: [{}]\n");
+        merge(
+            &mut base,
+            yaml("buttons:\n  South:\n    action: c\nplayers: []\n"),
+            0,
+        );
+        assert_eq!(
+            base,
+            yaml("buttons:\n  South:\n    action: c\nplayers: []\n")
+        );
+    }
+
+    #[test]
+    fn extends_and_include_merge_under_the_file() {
+        let directory = write_files(
+            "extends",
+            &[
+                (
+                    "base.yaml",
+                    "mouse_speed: 10\nbuttons:\n  South: a\n  East: b\n",
+                ),
+                ("parts/extra.json", r#"{"buttons": {"North": "c"}}"#),
+                (
+                    "config.yaml",
+                    "extends: base.yaml\ninclude: [parts/extra.json]\nbuttons:\n  East: d\n",
+                ),
+            ],
+        );
+        let merged = read_merged(&directory, "config.yaml").unwrap();
+        assert_eq!(
+            merged,
+            yaml("mouse_speed: 10\nbuttons:\n  South: a\n  East: d\n  North: c\n")
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn files_are_relative_to_the_configuration_naming_them() {
+        let directory = write_files(
+            "paths",
+            &[
+                (
+                    "base/base.yaml",
+                    "calibration_file: base.calibration\n\
+                     controller_mappings: [gamecontrollerdb.txt, \"0300,Pad,a:b0,\"]\n",
+                ),
+                ("config.yaml", "extends: base/base.yaml\n"),
+            ],
+        );
+        let merged = read_merged(&directory, "config.yaml").unwrap();
+        let base = directory.join("base");
+        let in_base = |file: &str| Value::from(base.join(file).to_str().unwrap());
+        assert_eq!(merged["calibration_file"], in_base("base.calibration"));
+        assert_eq!(
+            merged["controller_mappings"],
+            Value::Sequence(vec![
+                in_base("gamecontrollerdb.txt"),
+                Value::from("0300,Pad,a:b0,")
+            ])
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn extending_itself_is_an_error() {
+        let directory = write_files(
+            "cycle",
+            &[
+                ("a.yaml", "extends: b.yaml\n"),
+                ("b.yaml", "extends: a.yaml\n"),
+            ],
+        );
+        let error = read_merged(&directory, "a.yaml").unwrap_err();
+        assert!(error.to_string().contains("extends or includes itself"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn aliases_are_replaced_where_actions_are_bound() {
+        let configuration = resolved(
+            "actions:\n  jump: Space\n  run: [Shift, w]\n\
+             buttons:\n  South: jump\n  East: [run, e]\n  North:\n    action: x\n    on_press: jump\n\
+             axis:\n  LeftStickX: [jump, d]\n\
+             analog:\n  RightTrigger2:\n    - threshold: 0.5\n      action: jump\n\
+             players:\n  - buttons:\n      West: run\n",
+        )
+        .unwrap();
+        assert_eq!(
+            configuration,
+            yaml(
+                "buttons:\n  South: Space\n  East: [Shift, w, e]\n  North:\n    action: x\n    on_press: Space\n\
+                 axis:\n  LeftStickX: [Space, d]\n\
+                 analog:\n  RightTrigger2:\n    - threshold: 0.5\n      action: Space\n\
+                 players:\n  - buttons:\n      West: [Shift, w]\n"
+            )
+        );
+    }
+
+    #[test]
+    fn aliases_can_refer_to_each_other() {
+        let configuration =
+            resolved("actions:\n  a: b\n  b: [c, d]\nbuttons:\n  South: a\n").unwrap();
+        assert_eq!(configuration, yaml("buttons:\n  South: [c, d]\n"));
+    }
+
+    #[test]
+    fn aliases_in_a_loop_are_an_error() {
+        assert!(resolved("actions:\n  a: b\n  b: a\nbuttons:\n  South: a\n").is_err());
+    }
+
+    #[test]
+    fn aliases_of_extended_files_apply() {
+        let directory = write_files(
+            "aliases",
+            &[
+                ("base.yaml", "actions:\n  jump: Space\n"),
+                (
+                    "config.yaml",
+                    "extends: base.yaml\nbuttons:\n  South: jump\n",
+                ),
+            ],
+        );
+        let merged = read_merged(&directory, "config.yaml").unwrap();
+        assert_eq!(merged, yaml("buttons:\n  South: Space\n"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
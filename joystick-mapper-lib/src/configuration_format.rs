@@ -0,0 +1,58 @@
+use crate::error::MapperError;
+
+use serde_yaml::Value;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Language a configuration file is written in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigurationFormat {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl ConfigurationFormat {
+    /// Format told by the extension of a file, YAML unless it's `.json` or `.toml`
+    pub fn from_path(path: &str) -> Self {
+        let extension = Path::new(path)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("json") => ConfigurationFormat::Json,
+            Some("toml") => ConfigurationFormat::Toml,
+            _ => ConfigurationFormat::Yaml,
+        }
+    }
+
+    /// Parses the content of the file at `path`, in whatever format, as a
+    /// YAML value
+    pub(crate) fn parse(self, path: &str, content: &str) -> Result<Value, MapperError> {
+        match self {
+            ConfigurationFormat::Yaml => {
+                serde_yaml::from_str(content).map_err(|error| MapperError::yaml(path, error))
+            }
+            ConfigurationFormat::Json => serde_json::from_str(content)
+                .map_err(|error| MapperError::json(path, error.line(), error)),
+            ConfigurationFormat::Toml => {
+                toml::from_str(content).map_err(|error| MapperError::toml(path, error))
+            }
+        }
+    }
+}
+
+impl FromStr for ConfigurationFormat {
+    type Err = MapperError;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_lowercase().as_str() {
+            "yaml" | "yml" => Ok(ConfigurationFormat::Yaml),
+            "json" => Ok(ConfigurationFormat::Json),
+            "toml" => Ok(ConfigurationFormat::Toml),
+            _ => Err(MapperError::Usage(format!(
+                "Unknown format `{}`, expected yaml, json or toml",
+                format
+            ))),
+        }
+    }
+}
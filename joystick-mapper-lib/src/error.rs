@@ -0,0 +1,121 @@
+use std::error::Error;
+use std::{fmt, io};
+
+/// Everything that can go wrong while mapping
+#[derive(Debug)]
+pub enum MapperError {
+    /// A file couldn't be read or written
+    Io(String, io::Error),
+    /// A configuration, calibration, recording or script is invalid, with
+    /// where the problem is when known
+    Parse {
+        path: Option<String>,
+        line: Option<usize>,
+        column: Option<usize>,
+        message: String,
+    },
+    /// The gamepads can't be read
+    Input(Box<gilrs::Error>),
+    /// Keys and mouse events can't be sent
+    Output(String),
+    /// An action failed
+    Action(Box<dyn Error>),
+    /// Something the mapper needs before mapping, like the Ctrl+C handler,
+    /// couldn't be set up
+    Setup(Box<dyn Error>),
+    /// The command line is invalid
+    Usage(String),
+}
+
+impl MapperError {
+    pub(crate) fn yaml(path: &str, error: serde_yaml::Error) -> Self {
+        let location = error.location();
+        MapperError::Parse {
+            path: Some(path.to_string()),
+            line: location.as_ref().map(|location| location.line()),
+            column: location.as_ref().map(|location| location.column()),
+            message: without_location(error.to_string()),
+        }
+    }
+
+    pub(crate) fn json(path: &str, line: usize, error: serde_json::Error) -> Self {
+        MapperError::Parse {
+            path: Some(path.to_string()),
+            line: Some(line),
+            column: Some(error.column()),
+            message: without_location(error.to_string()),
+        }
+    }
+
+    pub(crate) fn toml(path: &str, error: toml::de::Error) -> Self {
+        let location = error.line_col();
+        MapperError::Parse {
+            path: Some(path.to_string()),
+            line: location.map(|(line, _)| line + 1),
+            column: location.map(|(_, column)| column + 1),
+            message: without_location(error.to_string()),
+        }
+    }
+}
+
+// the location is kept apart, not repeated at the end of the message
+pub(crate) fn without_location(mut message: String) -> String {
+    if let Some(index) = message.rfind(" at line ") {
+        message.truncate(index);
+    }
+    message
+}
+
+impl fmt::Display for MapperError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapperError::Io(path, error) => write!(f, "{}: {}", path, error),
+            MapperError::Parse {
+                path,
+                line,
+                column,
+                message,
+            } => {
+                match (path, line, column) {
+                    (Some(path), Some(line), Some(column)) => {
+                        write!(f, "{}:{}:{}: ", path, line, column)?
+                    }
+                    (Some(path), Some(line), None) => write!(f, "{}:{}: ", path, line)?,
+                    (Some(path), None, _) => write!(f, "{}: ", path)?,
+                    (None, Some(line), _) => write!(f, "line {}: ", line)?,
+                    (None, None, _) => {}
+                }
+                write!(f, "{}", message)
+            }
+            MapperError::Input(error) => write!(f, "Failed reading the gamepads: {}", error),
+            MapperError::Output(message) => write!(f, "Failed sending output: {}", message),
+            MapperError::Action(error) => write!(f, "Action failed: {}", error),
+            MapperError::Setup(error) => write!(f, "Setup failed: {}", error),
+            MapperError::Usage(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for MapperError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MapperError::Io(_, error) => Some(error),
+            MapperError::Input(error) => Some(error.as_ref()),
+            MapperError::Action(error) | MapperError::Setup(error) => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<gilrs::Error> for MapperError {
+    fn from(error: gilrs::Error) -> Self {
+        MapperError::Input(Box::new(error))
+    }
+}
+
+// actions of other crates fail with their own errors
+impl From<Box<dyn Error>> for MapperError {
+    fn from(error: Box<dyn Error>) -> Self {
+        MapperError::Action(error)
+    }
+}
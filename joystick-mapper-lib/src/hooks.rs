@@ -0,0 +1,40 @@
+use crate::error::MapperError;
+use crate::mapping_configuration::MappingSelection;
+
+use gilrs::{GamepadId, MappingSource, PowerInfo};
+
+/// Identity and status of a gamepad
+#[derive(Clone, Debug)]
+pub struct GamepadInfo {
+    pub id: GamepadId,
+    pub name: String,
+    pub uuid: String,
+    pub mapping_source: MappingSource,
+    pub power_info: PowerInfo,
+}
+
+/// Callbacks for embedders to react to what happens to the gamepads,
+/// every hook does nothing by default apart from `on_error` printing the error
+pub trait Hooks {
+    fn on_connected(&mut self, _gamepad: &GamepadInfo) {}
+
+    fn on_disconnected(&mut self, _gamepad: &GamepadInfo) {}
+
+    /// Called when the mappings used for a gamepad are selected, when it
+    /// connects or changes player
+    fn on_profile_changed(&mut self, _gamepad: &GamepadInfo, _selection: MappingSelection) {}
+
+    /// Called when a discharging gamepad goes under the `low_battery_threshold`,
+    /// once until it's charged again
+    fn on_low_battery(&mut self, _gamepad: &GamepadInfo, _charge: u8) {}
+
+    /// Called when a watched configuration file changed and was loaded again
+    fn on_configuration_reloaded(&mut self, _path: &str) {}
+
+    /// Called when performing an action fails, mapping goes on afterwards
+    fn on_error(&mut self, error: &MapperError) {
+        eprintln!("Error: {}", error);
+    }
+}
+
+impl Hooks for () {}
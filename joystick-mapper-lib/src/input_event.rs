@@ -0,0 +1,45 @@
+use gilrs::ev::Code;
+use gilrs::{Axis, Button, EventType};
+use serde::{Deserialize, Serialize};
+
+/// Gamepad event as mapped, coming from gilrs, a recording or a script,
+/// with the raw event code of the button or axis. Scripted inputs given by
+/// name have no code, so that only bindings by name match them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum InputEvent {
+    Connected,
+    Disconnected,
+    ButtonPressed(Button, Option<u32>),
+    ButtonReleased(Button, Option<u32>),
+    ButtonChanged(Button, f32, Option<u32>),
+    AxisChanged(Axis, f32, Option<u32>),
+}
+
+impl InputEvent {
+    /// Event for a gilrs one, if it matters to the mapping
+    pub fn from_gilrs(event: EventType) -> Option<InputEvent> {
+        match event {
+            EventType::Connected => Some(InputEvent::Connected),
+            EventType::Disconnected => Some(InputEvent::Disconnected),
+            EventType::ButtonPressed(button, code) => {
+                Some(InputEvent::ButtonPressed(button, raw_code(code)))
+            }
+            EventType::ButtonReleased(button, code) => {
+                Some(InputEvent::ButtonReleased(button, raw_code(code)))
+            }
+            EventType::ButtonChanged(button, amount, code) => {
+                Some(InputEvent::ButtonChanged(button, amount, raw_code(code)))
+            }
+            EventType::AxisChanged(axis, amount, code) => {
+                Some(InputEvent::AxisChanged(axis, amount, raw_code(code)))
+            }
+            // repeat is configured per binding and driven by exec_repeat_loop
+            EventType::ButtonRepeated(_, _) | EventType::Dropped => None,
+        }
+    }
+}
+
+/// Event code without its type, like 304 for BTN_SOUTH on Linux
+pub fn raw_code(code: Code) -> Option<u32> {
+    Some(code.into_u32() & 0xffff)
+}
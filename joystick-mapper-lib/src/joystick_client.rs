@@ -1,98 +1,866 @@
-use crate::action_client::{Action, ActionClient, InputState};
-
-use fxhash::FxHashMap;
-use gilrs::{Axis, Button, Event, Gamepad, GamepadId, Gilrs};
-use serde::{Deserialize, Serialize};
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct MappingConfiguration<A> {
-    pub buttons: FxHashMap<Button, A>,
-    pub axis: FxHashMap<Axis, [A; 2]>,
-    pub axis_sensitivity: Option<f32>,
-    pub mouse_speed: Option<f32>,
-}
+use crate::action_client::{Action, ActionClient, AxisSource, InputState};
+use crate::calibration::{AxisCalibration, AxisRange, AxisRanges, Calibration};
+use crate::configuration_files::ConfigurationFiles;
+use crate::configuration_format::ConfigurationFormat;
+use crate::error::MapperError;
+use crate::hooks::{GamepadInfo, Hooks};
+use crate::input_event::InputEvent;
+use crate::mapping_configuration::{
+    gamepad_uuid, AnalogBinding, MappingConfiguration, MappingSelection,
+};
+use crate::output::OutputBackend;
+use crate::player_slots::PlayerSlots;
+use crate::recording::{RecordedGamepad, Recorder, Replay};
+
+use fxhash::{FxHashMap, FxHashSet};
+use gilrs::{
+    Axis, Button, Event, Gamepad, GamepadId, Gilrs, GilrsBuilder, MappingSource, PowerInfo,
+};
+use serde::de::DeserializeOwned;
+use std::fs;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the mouse moves while a stick drives it
+const MOUSE_TICK: Duration = Duration::from_millis(15);
+/// Longest wait for a gamepad event: gilrs can't be woken up when the
+/// mapper is stopped, so `running` is checked at least this often
+const STOP_POLL: Duration = Duration::from_millis(100);
+/// How often a watched configuration file is checked for changes
+const RELOAD_POLL: Duration = Duration::from_millis(500);
+
+const CALIBRATED_AXES: [Axis; 6] = [
+    Axis::LeftStickX,
+    Axis::LeftStickY,
+    Axis::LeftZ,
+    Axis::RightStickX,
+    Axis::RightStickY,
+    Axis::RightZ,
+];
+
+const DPAD_BUTTONS: [Button; 4] = [
+    Button::DPadUp,
+    Button::DPadDown,
+    Button::DPadLeft,
+    Button::DPadRight,
+];
 
 pub struct JoystickClient<A: Action<S>, S> {
     gilrs: Gilrs,
     configuration: MappingConfiguration<A>,
     action_client: ActionClient<S>,
+    player_slots: PlayerSlots,
+    routes: FxHashMap<GamepadId, Route>,
+    analog_state: FxHashMap<(GamepadId, Button, Option<u32>, usize), InputState>,
+    repeat_state: FxHashMap<(GamepadId, Button, Option<u32>), Instant>,
+    low_battery: FxHashSet<GamepadId>,
+    next_battery_poll: Instant,
+    calibration: Calibration,
+    axis_calibration: FxHashMap<(GamepadId, Axis), AxisCalibration>,
+    dpad_state: FxHashMap<(GamepadId, Axis), Button>,
+    pressed_buttons: FxHashSet<(GamepadId, Button)>,
+    // gamepads known from a replay instead of gilrs
+    replayed_gamepads: FxHashMap<GamepadId, GamepadInfo>,
+    replayed_dpad_buttons: FxHashSet<GamepadId>,
+    // gamepads whose D-pad buttons come as events, from gilrs or a replay
+    dpad_button_events: FxHashSet<GamepadId>,
+    // time events happen at, the recording time during a replay
+    now: Instant,
+    next_mouse_tick: Instant,
+    watched_configuration: Option<WatchedConfiguration<A>>,
+}
+
+// configuration reloaded when one of its files changes, `load` keeps the
+// bounds needed to parse the actions out of the rest of the client
+struct WatchedConfiguration<A> {
+    path: String,
+    format: ConfigurationFormat,
+    modified: Vec<(String, Option<SystemTime>)>,
+    next_check: Instant,
+    load: fn(&str, ConfigurationFormat) -> Result<MappingConfiguration<A>, MapperError>,
+}
+
+// where the events of a gamepad go: its mappings, its player and the part
+// of a virtual gamepad it is
+#[derive(Clone, Copy)]
+struct Route {
+    selection: MappingSelection,
+    virtual_part: Option<usize>,
 }
 
 impl<A: Action<S>, S> JoystickClient<A, S> {
-    pub fn new(configuration: MappingConfiguration<A>, state: S) -> JoystickClient<A, S> {
-        let gilrs = Gilrs::new().unwrap();
-        let mouse_speed = configuration.mouse_speed.unwrap_or(20.0);
-        let action_client = ActionClient::new(state, mouse_speed);
-        JoystickClient {
+    pub fn new(
+        configuration: MappingConfiguration<A>,
+        state: S,
+    ) -> Result<JoystickClient<A, S>, MapperError> {
+        let gilrs = match GilrsBuilder::new()
+            .add_mappings(&configuration.sdl_mappings()?)
+            .build()
+        {
+            Ok(gilrs) => gilrs,
+            // without gamepad support there are still replays and scripts
+            Err(gilrs::Error::NotImplemented(gilrs)) => gilrs,
+            Err(error) => return Err(error.into()),
+        };
+        let (mouse_speed, scroll_speed) = speeds(&configuration);
+        let action_client = ActionClient::new(state, mouse_speed, scroll_speed);
+        let player_slots = PlayerSlots::new(configuration.players.len());
+        let calibration = Calibration::load(&configuration.calibration_path())?;
+        Ok(JoystickClient {
             gilrs,
             configuration,
             action_client,
+            player_slots,
+            routes: FxHashMap::default(),
+            analog_state: FxHashMap::default(),
+            repeat_state: FxHashMap::default(),
+            low_battery: FxHashSet::default(),
+            next_battery_poll: Instant::now(),
+            calibration,
+            axis_calibration: FxHashMap::default(),
+            dpad_state: FxHashMap::default(),
+            pressed_buttons: FxHashSet::default(),
+            replayed_gamepads: FxHashMap::default(),
+            replayed_dpad_buttons: FxHashSet::default(),
+            dpad_button_events: FxHashSet::default(),
+            now: Instant::now(),
+            next_mouse_tick: Instant::now(),
+            watched_configuration: None,
+        })
+    }
+
+    /// Replaces the configuration, releasing everything held down with the
+    /// previous one. Gamepads get their mappings again on their next event,
+    /// `controller_mappings` only apply to a new client.
+    pub fn set_configuration(
+        &mut self,
+        configuration: MappingConfiguration<A>,
+    ) -> Result<(), MapperError> {
+        let calibration = Calibration::load(&configuration.calibration_path())?;
+        let released = self.release_all();
+        if configuration.players.len() != self.configuration.players.len() {
+            self.player_slots = PlayerSlots::new(configuration.players.len());
         }
+        let (mouse_speed, scroll_speed) = speeds(&configuration);
+        self.action_client.set_speeds(mouse_speed, scroll_speed);
+        self.configuration = configuration;
+        self.calibration = calibration;
+        self.routes.clear();
+        self.axis_calibration.clear();
+        self.next_battery_poll = Instant::now();
+        released
+    }
+
+    /// Loads the configuration again whenever the file at `path`, or one it
+    /// extends or includes, changes. An invalid configuration is reported to
+    /// `on_error` and the current one kept.
+    pub fn watch_configuration(&mut self, path: &str, format: ConfigurationFormat)
+    where
+        A: DeserializeOwned,
+    {
+        self.watched_configuration = Some(WatchedConfiguration {
+            path: path.to_string(),
+            format,
+            modified: modified_times(path, format),
+            next_check: Instant::now() + RELOAD_POLL,
+            load: MappingConfiguration::load_as,
+        });
+    }
+
+    /// Sends the output of the actions somewhere else than the system keyboard and mouse
+    pub fn set_output(&mut self, output: Box<dyn OutputBackend>) {
+        self.action_client.output = output;
     }
 
     pub fn gamepads(&self) -> Vec<(GamepadId, Gamepad)> {
         self.gilrs.gamepads().collect::<Vec<(GamepadId, Gamepad)>>()
     }
 
-    pub fn exec_event_loop(
+    pub fn gamepads_info(&self) -> Vec<GamepadInfo> {
+        self.gilrs
+            .gamepads()
+            .map(|(id, _)| self.gamepad_info(id))
+            .collect()
+    }
+
+    pub fn gamepad_info(&self, id: GamepadId) -> GamepadInfo {
+        if let Some(gamepad) = self.replayed_gamepads.get(&id) {
+            return gamepad.clone();
+        }
+        let gamepad = self.gilrs.gamepad(id);
+        GamepadInfo {
+            id,
+            name: gamepad.name().to_string(),
+            uuid: gamepad_uuid(&gamepad),
+            mapping_source: gamepad.mapping_source(),
+            power_info: gamepad.power_info(),
+        }
+    }
+
+    pub fn exec_event_loop(&mut self, hooks: &mut dyn Hooks) -> Result<(), MapperError> {
+        self.now = Instant::now();
+        while let Some(Event { id, event, .. }) = self.gilrs.next_event() {
+            if let Some(event) = InputEvent::from_gilrs(event) {
+                self.dispatch_event(id, event, hooks);
+            }
+        }
+        if let Err(error) = self.exec_repeat_loop() {
+            hooks.on_error(&error);
+        }
+        if self.now >= self.next_mouse_tick {
+            if let Err(error) = self.action_client.exec_mouse_loop() {
+                hooks.on_error(&error);
+            }
+            self.next_mouse_tick = (self.next_mouse_tick + MOUSE_TICK).max(self.now);
+        }
+        self.exec_battery_loop(hooks);
+        self.exec_reload_loop(hooks);
+        Ok(())
+    }
+
+    /// Maps the gamepad events until `running` is false, releasing everything
+    /// held down before returning.
+    ///
+    /// Events are mapped as soon as they come, in between the loop only
+    /// wakes up when a timer is due or to check `running`.
+    pub fn run(&mut self, hooks: &mut dyn Hooks, running: &AtomicBool) -> Result<(), MapperError> {
+        while running.load(Ordering::SeqCst) {
+            let stop_check = Instant::now() + STOP_POLL;
+            let deadline = self
+                .next_timer()
+                .map_or(stop_check, |timer| timer.min(stop_check));
+            if let Some(Event { id, event, .. }) = self.wait_event(deadline) {
+                self.now = Instant::now();
+                if let Some(event) = InputEvent::from_gilrs(event) {
+                    self.dispatch_event(id, event, hooks);
+                }
+            }
+            if let Err(error) = self.exec_event_loop(hooks) {
+                let _ = self.release_all();
+                return Err(error);
+            }
+        }
+        self.release_all()
+    }
+
+    // when the next repeat, mouse move, battery poll or reload check is due
+    fn next_timer(&self) -> Option<Instant> {
+        let mouse_tick = if self.action_client.is_mouse_moving() {
+            Some(self.next_mouse_tick)
+        } else {
+            None
+        };
+        let reload = self
+            .watched_configuration
+            .as_ref()
+            .map(|watched| watched.next_check);
+        self.repeat_state
+            .values()
+            .copied()
+            .chain(mouse_tick)
+            .chain(reload)
+            .chain(Some(self.next_battery_poll))
+            .min()
+    }
+
+    // waits for a gamepad event until `deadline`
+    fn wait_event(&mut self, deadline: Instant) -> Option<Event> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let event = self.gilrs.next_event_blocking(Some(timeout));
+        // without gamepad support gilrs returns at once
+        if event.is_none() {
+            thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        }
+        event
+    }
+
+    /// Writes the gamepad events to the recorder instead of mapping them,
+    /// as they come, until `running` is false
+    pub fn record<W: Write>(
         &mut self,
-        on_connected: Option<&dyn Fn() -> ()>,
-        on_disconnected: Option<&dyn Fn() -> ()>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        while let Some(Event { event, .. }) = self.gilrs.next_event() {
-            match event {
-                gilrs::EventType::ButtonPressed(button, _) => {
-                    if let Some(action) = self.configuration.buttons.get(&button) {
+        recorder: &mut Recorder<W>,
+        running: &AtomicBool,
+    ) -> Result<(), MapperError> {
+        while running.load(Ordering::SeqCst) {
+            if let Some(event) = self.wait_event(Instant::now() + STOP_POLL) {
+                self.record_event(recorder, event)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the pending gamepad events to the recorder instead of mapping them
+    pub fn exec_record_loop<W: Write>(
+        &mut self,
+        recorder: &mut Recorder<W>,
+    ) -> Result<(), MapperError> {
+        while let Some(event) = self.gilrs.next_event() {
+            self.record_event(recorder, event)?;
+        }
+        Ok(())
+    }
+
+    fn record_event<W: Write>(
+        &mut self,
+        recorder: &mut Recorder<W>,
+        Event { id, event, time }: Event,
+    ) -> Result<(), MapperError> {
+        let event = match InputEvent::from_gilrs(event) {
+            Some(event) => event,
+            None => return Ok(()),
+        };
+        let gamepad = if recorder.knows(id) {
+            None
+        } else {
+            let gamepad = self.gilrs.gamepad(id);
+            Some(RecordedGamepad {
+                name: gamepad.name().to_string(),
+                uuid: gamepad_uuid(&gamepad),
+                dpad_buttons: has_dpad_buttons(&gamepad),
+            })
+        };
+        recorder.record(id, event, time, gamepad)
+    }
+
+    /// Maps the events of a replay until it's finished or `running` is false,
+    /// releasing everything held down before returning
+    pub fn replay(
+        &mut self,
+        replay: &mut Replay,
+        hooks: &mut dyn Hooks,
+        running: &AtomicBool,
+    ) -> Result<(), MapperError> {
+        while running.load(Ordering::SeqCst) && !replay.is_finished() {
+            if let Err(error) = self.exec_replay_loop(replay, hooks) {
+                let _ = self.release_all();
+                return Err(error);
+            }
+        }
+        self.release_all()
+    }
+
+    /// Maps the events of the next tick of a replay, like `exec_event_loop`
+    /// does with the gamepad ones
+    pub fn exec_replay_loop(
+        &mut self,
+        replay: &mut Replay,
+        hooks: &mut dyn Hooks,
+    ) -> Result<(), MapperError> {
+        let events = replay.advance().to_vec();
+        self.now = replay.now();
+        for recorded_event in events {
+            let id = recorded_event.id;
+            if let Some(gamepad) = recorded_event.gamepad {
+                if gamepad.dpad_buttons {
+                    self.replayed_dpad_buttons.insert(id);
+                } else {
+                    self.replayed_dpad_buttons.remove(&id);
+                }
+                self.replayed_gamepads
+                    .insert(id, replayed_gamepad(id, gamepad.name, gamepad.uuid));
+            } else {
+                // recordings cut short may not start with the gamepad identity
+                self.replayed_gamepads.entry(id).or_insert_with(|| {
+                    let index: usize = id.into();
+                    let name = format!("Replayed gamepad {}", index);
+                    replayed_gamepad(id, name, format!("{:032x}", index))
+                });
+            }
+            self.dispatch_event(id, recorded_event.event, hooks);
+        }
+        self.exec_timers(hooks);
+        replay.wait();
+        Ok(())
+    }
+
+    fn dispatch_event(&mut self, id: GamepadId, event: InputEvent, hooks: &mut dyn Hooks) {
+        for event in self.with_dpad_buttons(id, event) {
+            if let Err(error) = self.handle_event(id, event, hooks) {
+                hooks.on_error(&error);
+            }
+        }
+    }
+
+    fn exec_timers(&mut self, hooks: &mut dyn Hooks) {
+        if let Err(error) = self.exec_repeat_loop() {
+            hooks.on_error(&error);
+        }
+        if let Err(error) = self.action_client.exec_mouse_loop() {
+            hooks.on_error(&error);
+        }
+    }
+
+    fn handle_event(
+        &mut self,
+        id: GamepadId,
+        event: InputEvent,
+        hooks: &mut dyn Hooks,
+    ) -> Result<(), MapperError> {
+        match event {
+            InputEvent::ButtonPressed(button, _) => {
+                self.pressed_buttons.insert((id, button));
+            }
+            InputEvent::ButtonReleased(button, _) => {
+                self.pressed_buttons.remove(&(id, button));
+            }
+            _ => {}
+        }
+        if let InputEvent::ButtonPressed(button, _) = event {
+            if self.is_player_combo(id, button) {
+                self.next_player(id, hooks);
+                return Ok(());
+            }
+        }
+        if let InputEvent::Connected = event {
+            hooks.on_connected(&self.gamepad_info(id));
+        }
+        let route = self.route(id, hooks);
+        if !self.is_provided(route, &event) {
+            return Ok(());
+        }
+        self.action_client.select_slot(slot(id));
+        let mapping = self.configuration.mapping(route.selection);
+        match event {
+            InputEvent::ButtonPressed(button, code) => {
+                if let Some(binding) = mapping.button_binding(button, code) {
+                    for action in binding.actions() {
                         self.action_client
                             .perform_action(action, InputState::Down, None)?;
                     }
+                    for action in binding.on_press() {
+                        self.action_client.tap_action(action)?;
+                    }
+                    if let Some(repeat) = binding.repeat() {
+                        let delay = Duration::from_millis(repeat.delay);
+                        self.repeat_state
+                            .insert((id, button, code), self.now + delay);
+                    }
                 }
-                gilrs::EventType::ButtonReleased(button, _) => {
-                    if let Some(action) = self.configuration.buttons.get(&button) {
+            }
+            InputEvent::ButtonReleased(button, code) => {
+                self.repeat_state.remove(&(id, button, code));
+                if let Some(binding) = mapping.button_binding(button, code) {
+                    for action in binding.actions().iter().rev() {
                         self.action_client
                             .perform_action(action, InputState::Up, None)?;
                     }
+                    for action in binding.on_release() {
+                        self.action_client.tap_action(action)?;
+                    }
                 }
-                gilrs::EventType::AxisChanged(axis, amount, _) => {
-                    if let Some([negative_action, positive_action]) =
-                        self.configuration.axis.get(&axis)
-                    {
-                        let axis_sensitivity =
-                            self.configuration.axis_sensitivity.unwrap_or(0.3_f32);
-                        let negative_input_state = if amount >= -axis_sensitivity {
-                            InputState::Up
-                        } else {
-                            InputState::Down
-                        };
-                        let positive_input_state = if amount <= axis_sensitivity {
-                            InputState::Up
-                        } else {
-                            InputState::Down
-                        };
-                        self.action_client.perform_action(
-                            negative_action,
-                            negative_input_state,
-                            Some(amount),
-                        )?;
-                        self.action_client.perform_action(
-                            positive_action,
-                            positive_input_state,
-                            Some(amount),
-                        )?;
+            }
+            InputEvent::AxisChanged(axis, amount, code) => {
+                let amount = self.calibrate(id, axis, amount);
+                if let Some([negative_action, positive_action]) = mapping.axis_binding(axis, code) {
+                    let axis_sensitivity = self.configuration.axis_sensitivity.unwrap_or(0.3_f32);
+                    let negative_input_state = if amount >= -axis_sensitivity {
+                        InputState::Up
+                    } else {
+                        InputState::Down
+                    };
+                    let positive_input_state = if amount <= axis_sensitivity {
+                        InputState::Up
+                    } else {
+                        InputState::Down
+                    };
+                    let source = |positive| AxisSource::Axis {
+                        gamepad: id,
+                        axis,
+                        code,
+                        positive,
+                    };
+                    self.action_client.perform_axis_action(
+                        negative_action,
+                        source(false),
+                        negative_input_state,
+                        amount,
+                    )?;
+                    self.action_client.perform_axis_action(
+                        positive_action,
+                        source(true),
+                        positive_input_state,
+                        amount,
+                    )?;
+                }
+            }
+            InputEvent::ButtonChanged(button, amount, code) => {
+                self.exec_analog_binding(id, route, button, code, amount)?;
+            }
+            // connection is announced before selecting the mapping
+            InputEvent::Connected => {}
+            InputEvent::Disconnected => {
+                let released = self.action_client.release_slot(slot(id));
+                self.clear_gamepad_state(id);
+                self.dpad_button_events.remove(&id);
+                self.player_slots.disconnect(id);
+                hooks.on_disconnected(&self.gamepad_info(id));
+                released?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Releases everything the mapping is holding down, to be called before exiting
+    pub fn release_all(&mut self) -> Result<(), MapperError> {
+        self.repeat_state.clear();
+        self.analog_state.clear();
+        self.action_client.release_all()
+    }
+
+    // the route of a gamepad is decided once, until it disconnects or changes player
+    fn route(&mut self, id: GamepadId, hooks: &mut dyn Hooks) -> Route {
+        if let Some(route) = self.routes.get(&id) {
+            return *route;
+        }
+        let gamepad = self.gamepad_info(id);
+        let virtual_part = self.configuration.virtual_gamepad_part(&gamepad);
+        let virtual_gamepad = virtual_part.map(|(virtual_gamepad, _)| virtual_gamepad);
+        // the parts of a virtual gamepad play together as the player of the first one
+        let virtual_gamepad_player = virtual_gamepad.and_then(|virtual_gamepad| {
+            self.routes
+                .values()
+                .find(|route| route.selection.virtual_gamepad == Some(virtual_gamepad))
+                .map(|route| route.selection.player)
+        });
+        let player = match virtual_gamepad_player {
+            Some(player) => player,
+            None => self.player_slots.assign(id, &gamepad.uuid),
+        };
+        let route = Route {
+            selection: MappingSelection {
+                virtual_gamepad,
+                gamepad: self.configuration.gamepad_mapping_index(&gamepad),
+                player,
+            },
+            virtual_part: virtual_part.map(|(_, part)| part),
+        };
+        self.routes.insert(id, route);
+        for axis in &CALIBRATED_AXES {
+            if let Some(axis_calibration) = self.calibration.axis(&gamepad.uuid, *axis) {
+                self.axis_calibration.insert((id, *axis), axis_calibration);
+            }
+        }
+        hooks.on_profile_changed(&gamepad, route.selection);
+        route
+    }
+
+    // parts of a virtual gamepad only provide the buttons and axis listed for them
+    fn is_provided(&self, route: Route, event: &InputEvent) -> bool {
+        let part = match (route.selection.virtual_gamepad, route.virtual_part) {
+            (Some(virtual_gamepad), Some(part)) => {
+                &self.configuration.virtual_gamepads[virtual_gamepad].parts[part]
+            }
+            _ => return true,
+        };
+        match *event {
+            InputEvent::ButtonPressed(button, code)
+            | InputEvent::ButtonReleased(button, code)
+            | InputEvent::ButtonChanged(button, _, code) => part.provides_button(button, code),
+            InputEvent::AxisChanged(axis, _, code) => part.provides_axis(axis, code),
+            _ => true,
+        }
+    }
+
+    fn clear_gamepad_state(&mut self, id: GamepadId) {
+        self.routes.remove(&id);
+        self.repeat_state
+            .retain(|(gamepad, _, _), _| *gamepad != id);
+        self.analog_state
+            .retain(|(gamepad, _, _, _), _| *gamepad != id);
+        self.axis_calibration
+            .retain(|(gamepad, _), _| *gamepad != id);
+        self.dpad_state.retain(|(gamepad, _), _| *gamepad != id);
+        self.pressed_buttons.retain(|(gamepad, _)| *gamepad != id);
+    }
+
+    fn is_player_combo(&self, id: GamepadId, button: Button) -> bool {
+        let player_combo = &self.configuration.player_combo;
+        player_combo.contains(&button)
+            && player_combo
+                .iter()
+                .all(|combo_button| self.pressed_buttons.contains(&(id, *combo_button)))
+    }
+
+    fn next_player(&mut self, id: GamepadId, hooks: &mut dyn Hooks) {
+        if let Some((player, next_player)) = self.player_slots.next(id) {
+            for moved_player in &[player, next_player] {
+                if let Some(gamepad) = self.player_slots.gamepad(*moved_player) {
+                    if let Err(error) = self.action_client.release_slot(slot(gamepad)) {
+                        hooks.on_error(&error);
+                    }
+                    self.clear_gamepad_state(gamepad);
+                    self.route(gamepad, hooks);
+                }
+            }
+        }
+    }
+
+    // some gamepads report the D-pad as hat axis, pressing the D-pad buttons
+    // as well lets the same mapping work on all of them. gilrs already turns
+    // the hat into buttons for most of them, dropping the axis events, this
+    // covers the ones it leaves alone. The pressed buttons have no code, so
+    // that bindings by code only match real events.
+    fn with_dpad_buttons(&mut self, id: GamepadId, event: InputEvent) -> Vec<InputEvent> {
+        if let InputEvent::ButtonPressed(button, _) = event {
+            if DPAD_BUTTONS.contains(&button) {
+                self.dpad_button_events.insert(id);
+            }
+        }
+        let mut events = vec![event];
+        if !self.configuration.dpad_axis_as_buttons.unwrap_or(true)
+            || self.dpad_buttons(id)
+            || self.dpad_button_events.contains(&id)
+        {
+            return events;
+        }
+        let (axis, amount, [negative_button, positive_button]) = match event {
+            InputEvent::AxisChanged(Axis::DPadX, amount, _) => {
+                (Axis::DPadX, amount, [Button::DPadLeft, Button::DPadRight])
+            }
+            InputEvent::AxisChanged(Axis::DPadY, amount, _) => {
+                (Axis::DPadY, amount, [Button::DPadDown, Button::DPadUp])
+            }
+            _ => return events,
+        };
+        let pressed = if amount >= 0.5 {
+            Some(positive_button)
+        } else if amount <= -0.5 {
+            Some(negative_button)
+        } else {
+            None
+        };
+        let held = self.dpad_state.get(&(id, axis)).copied();
+        if held == pressed {
+            return events;
+        }
+        if let Some(button) = held {
+            self.dpad_state.remove(&(id, axis));
+            events.push(InputEvent::ButtonReleased(button, None));
+        }
+        if let Some(button) = pressed {
+            self.dpad_state.insert((id, axis), button);
+            events.push(InputEvent::ButtonPressed(button, None));
+        }
+        events
+    }
+
+    fn dpad_buttons(&self, id: GamepadId) -> bool {
+        if self.replayed_gamepads.contains_key(&id) {
+            return self.replayed_dpad_buttons.contains(&id);
+        }
+        match self.gilrs.connected_gamepad(id) {
+            Some(gamepad) => has_dpad_buttons(&gamepad),
+            None => false,
+        }
+    }
+
+    fn calibrate(&self, id: GamepadId, axis: Axis, amount: f32) -> f32 {
+        match self.axis_calibration.get(&(id, axis)) {
+            Some(axis_calibration) => axis_calibration.apply(amount),
+            None => amount,
+        }
+    }
+
+    /// Samples the values of the axis of every gamepad for a while, to be
+    /// turned into a `Calibration`
+    pub fn sample_axes(&mut self, duration: Duration) -> AxisRanges {
+        let mut ranges = AxisRanges::default();
+        let end = Instant::now() + duration;
+        while Instant::now() < end {
+            // events only matter to keep the gamepad states up to date
+            while self.gilrs.next_event().is_some() {}
+            for (_, gamepad) in self.gilrs.gamepads() {
+                let uuid = gamepad_uuid(&gamepad);
+                for axis in &CALIBRATED_AXES {
+                    if gamepad.axis_code(*axis).is_none() {
+                        continue;
                     }
+                    let value = gamepad.value(*axis);
+                    ranges
+                        .entry((uuid.clone(), *axis))
+                        .and_modify(|range| range.add(value))
+                        .or_insert_with(|| AxisRange::new(value));
+                }
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        ranges
+    }
+
+    fn exec_battery_loop(&mut self, hooks: &mut dyn Hooks) {
+        let now = Instant::now();
+        if now < self.next_battery_poll {
+            return;
+        }
+        let poll_interval = self.configuration.battery_poll_interval.unwrap_or(60);
+        self.next_battery_poll = now + Duration::from_secs(poll_interval);
+        let threshold = self.configuration.low_battery_threshold.unwrap_or(15);
+        for gamepad in self.gamepads_info() {
+            match gamepad.power_info {
+                PowerInfo::Discharging(charge) if charge <= threshold => {
+                    if self.low_battery.insert(gamepad.id) {
+                        hooks.on_low_battery(&gamepad, charge);
+                    }
+                }
+                _ => {
+                    self.low_battery.remove(&gamepad.id);
+                }
+            }
+        }
+    }
+
+    fn exec_reload_loop(&mut self, hooks: &mut dyn Hooks) {
+        let watched = match &mut self.watched_configuration {
+            Some(watched) if self.now >= watched.next_check => watched,
+            _ => return,
+        };
+        watched.next_check = self.now + RELOAD_POLL;
+        let changed = watched
+            .modified
+            .iter()
+            .any(|(path, modified)| modified_time(path) != *modified);
+        if !changed {
+            return;
+        }
+        watched.modified = modified_times(&watched.path, watched.format);
+        let path = watched.path.clone();
+        let configuration = match (watched.load)(&path, watched.format) {
+            Ok(configuration) => configuration,
+            Err(error) => return hooks.on_error(&error),
+        };
+        let released = self.set_configuration(configuration);
+        hooks.on_configuration_reloaded(&path);
+        if let Err(error) = released {
+            hooks.on_error(&error);
+        }
+    }
+
+    fn exec_repeat_loop(&mut self) -> Result<(), MapperError> {
+        let now = self.now;
+        for ((id, button, code), next_repeat) in self.repeat_state.iter_mut() {
+            if *next_repeat > now {
+                continue;
+            }
+            let route = match self.routes.get(id) {
+                Some(route) => *route,
+                None => continue,
+            };
+            self.action_client.select_slot(slot(*id));
+            let mapping = self.configuration.mapping(route.selection);
+            if let Some(binding) = mapping.button_binding(*button, *code) {
+                for action in binding.actions().iter().rev() {
+                    self.action_client
+                        .perform_action(action, InputState::Up, None)?;
+                }
+                for action in binding.actions() {
+                    self.action_client
+                        .perform_action(action, InputState::Down, None)?;
                 }
-                gilrs::EventType::ButtonRepeated(_, _) => {}
-                gilrs::EventType::ButtonChanged(_, _, _) => {}
-                gilrs::EventType::Connected => {
-                    on_connected.and_then(|cb| Some(cb())).unwrap_or(())
+                for action in binding.on_press() {
+                    self.action_client.tap_action(action)?;
                 }
-                gilrs::EventType::Disconnected => {
-                    on_disconnected.and_then(|cb| Some(cb())).unwrap_or(())
+                // repeats keep to their schedule however late the loop is,
+                // the ones missed while it was far behind are skipped
+                if let Some(repeat) = binding.repeat() {
+                    let interval = repeat.interval().as_nanos();
+                    let skipped = (now - *next_repeat).as_nanos() / interval + 1;
+                    *next_repeat += Duration::from_nanos((skipped * interval) as u64);
                 }
-                gilrs::EventType::Dropped => {}
             }
         }
-        self.action_client.exec_mouse_loop();
         Ok(())
     }
+
+    fn exec_analog_binding(
+        &mut self,
+        id: GamepadId,
+        route: Route,
+        button: Button,
+        code: Option<u32>,
+        amount: f32,
+    ) -> Result<(), MapperError> {
+        let mapping = self.configuration.mapping(route.selection);
+        match mapping.analog_binding(button, code) {
+            Some(AnalogBinding::Stages(stages)) => {
+                for (index, stage) in stages.iter().enumerate() {
+                    let stage_state = self
+                        .analog_state
+                        .entry((id, button, code, index))
+                        .or_insert(InputState::Up);
+                    let release_threshold = stage.release_threshold.unwrap_or(stage.threshold);
+                    let input_state = match *stage_state {
+                        InputState::Up if amount >= stage.threshold => InputState::Down,
+                        InputState::Down if amount < release_threshold => InputState::Up,
+                        _ => continue,
+                    };
+                    *stage_state = input_state;
+                    self.action_client
+                        .perform_action(&stage.action, input_state, None)?;
+                }
+            }
+            Some(AnalogBinding::Axis(action)) => {
+                let input_state = if amount > 0_f32 {
+                    InputState::Down
+                } else {
+                    InputState::Up
+                };
+                let source = AxisSource::Button {
+                    gamepad: id,
+                    button,
+                    code,
+                };
+                self.action_client
+                    .perform_axis_action(action, source, input_state, amount)?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+fn replayed_gamepad(id: GamepadId, name: String, uuid: String) -> GamepadInfo {
+    GamepadInfo {
+        id,
+        name,
+        uuid,
+        mapping_source: MappingSource::None,
+        power_info: PowerInfo::Unknown,
+    }
+}
+
+fn has_dpad_buttons(gamepad: &Gamepad) -> bool {
+    DPAD_BUTTONS
+        .iter()
+        .any(|button| gamepad.button_code(*button).is_some())
+}
+
+// every gamepad holds its actions down in its own slot, so that one
+// disconnecting or changing player only releases what it was holding
+fn slot(id: GamepadId) -> usize {
+    id.into()
+}
+
+fn speeds<A>(configuration: &MappingConfiguration<A>) -> (f32, f32) {
+    (
+        configuration.mouse_speed.unwrap_or(20.0),
+        configuration.scroll_speed.unwrap_or(0.2),
+    )
+}
+
+fn modified_times(path: &str, format: ConfigurationFormat) -> Vec<(String, Option<SystemTime>)> {
+    ConfigurationFiles::paths(path, format)
+        .into_iter()
+        .map(|path| {
+            let modified = modified_time(&path);
+            (path, modified)
+        })
+        .collect()
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
 }
@@ -1,6 +1,37 @@
 pub mod action_client;
+pub mod calibration;
+pub mod check;
+mod configuration_files;
+pub mod configuration_format;
+pub mod error;
+pub mod hooks;
+pub mod input_event;
 pub mod joystick_client;
+pub mod mapping_configuration;
+pub mod output;
+mod player_slots;
+pub mod profiles;
+pub mod recording;
+pub mod runner;
+pub mod script;
 
-pub use action_client::{Action, ActionClient, InputState, MouseAction};
+pub use action_client::{Action, ActionClient, AxisSource, InputState, MouseAction};
+pub use calibration::{AxisCalibration, AxisRange, AxisRanges, Calibration};
+pub use check::{check_configuration, Diagnostic, Severity};
+pub use configuration_format::ConfigurationFormat;
 pub use enigo::{Key, MouseButton};
-pub use joystick_client::{JoystickClient, MappingConfiguration};
+pub use error::MapperError;
+pub use gilrs::{GamepadId, MappingSource, PowerInfo};
+pub use hooks::{GamepadInfo, Hooks};
+pub use input_event::{raw_code, InputEvent};
+pub use joystick_client::JoystickClient;
+pub use mapping_configuration::{
+    gamepad_uuid, AnalogBinding, AnalogStage, Binding, DetailedBinding, GamepadMapping,
+    GamepadSelector, Input, Mapping, MappingConfiguration, MappingSelection, Repeat,
+    VirtualGamepad, VirtualGamepadPart,
+};
+pub use output::{LogOutput, OutputBackend};
+pub use profiles::{list_profiles, Profile};
+pub use recording::{RecordedEvent, RecordedGamepad, Recorder, Replay};
+pub use runner::{run, PrintHooks, RunOptions};
+pub use script::{parse_script, Script};
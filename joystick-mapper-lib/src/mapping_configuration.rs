@@ -0,0 +1,505 @@
+use crate::check::{check_with_aliases, Severity};
+use crate::configuration_files::ConfigurationFiles;
+use crate::configuration_format::ConfigurationFormat;
+use crate::error::{without_location, MapperError};
+use crate::hooks::GamepadInfo;
+use crate::profiles::configuration_directory;
+
+use fxhash::FxHashMap;
+use gilrs::{Axis, Button, Gamepad};
+use serde::de::{self, DeserializeOwned, IntoDeserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::hash::Hash;
+use std::path::Path;
+use std::time::Duration;
+use std::{fmt, fs};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MappingConfiguration<A> {
+    /// What the configuration is for, shown when listing profiles
+    pub description: Option<String>,
+    #[serde(flatten)]
+    pub mapping: Mapping<A>,
+    #[serde(default = "Vec::new")]
+    pub gamepads: Vec<GamepadMapping<A>>,
+    #[serde(default = "Vec::new")]
+    pub virtual_gamepads: Vec<VirtualGamepad<A>>,
+    #[serde(default = "Vec::new")]
+    pub players: Vec<Mapping<A>>,
+    /// Buttons pressed together to move a gamepad to the next player
+    #[serde(default = "Vec::new")]
+    pub player_combo: Vec<Button>,
+    /// Press the D-pad buttons when the D-pad is reported as `DPadX` and `DPadY` axis, true by default
+    pub dpad_axis_as_buttons: Option<bool>,
+    pub axis_sensitivity: Option<f32>,
+    pub mouse_speed: Option<f32>,
+    pub scroll_speed: Option<f32>,
+    /// SDL gamecontrollerdb.txt lines or paths of files containing them
+    #[serde(default = "Vec::new")]
+    pub controller_mappings: Vec<String>,
+    /// File storing the axis calibrations made with `calibrate`
+    pub calibration_file: Option<String>,
+    /// Battery percentage under which a gamepad is reported as low on battery
+    pub low_battery_threshold: Option<u8>,
+    /// Seconds between battery checks
+    pub battery_poll_interval: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Mapping<A> {
+    #[serde(default = "FxHashMap::default")]
+    pub buttons: FxHashMap<Input<Button>, Binding<A>>,
+    #[serde(default = "FxHashMap::default")]
+    pub axis: FxHashMap<Input<Axis>, [A; 2]>,
+    #[serde(default = "FxHashMap::default")]
+    pub analog: FxHashMap<Input<Button>, AnalogBinding<A>>,
+}
+
+/// Button or axis by gilrs name, or by raw event code written as `code:304`
+/// for the ones gilrs reports as `Unknown`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Input<T> {
+    Named(T),
+    Code(u32),
+}
+
+/// Mapping used instead of the default one for the gamepads matching the selector
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GamepadMapping<A> {
+    #[serde(flatten)]
+    pub selector: GamepadSelector,
+    #[serde(flatten)]
+    pub mapping: Mapping<A>,
+}
+
+/// Logical gamepad made of parts of several physical ones, like a pair of Joy-Cons
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VirtualGamepad<A> {
+    pub parts: Vec<VirtualGamepadPart>,
+    #[serde(flatten)]
+    pub mapping: Mapping<A>,
+}
+
+/// Physical gamepad providing the listed buttons and axis, or all of them if none are listed
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct VirtualGamepadPart {
+    #[serde(flatten)]
+    pub selector: GamepadSelector,
+    #[serde(default = "Vec::new")]
+    pub buttons: Vec<Input<Button>>,
+    #[serde(default = "Vec::new")]
+    pub axis: Vec<Input<Axis>>,
+}
+
+/// Which mappings of the configuration apply to a gamepad, as indexes in
+/// `virtual_gamepads`, `gamepads` and `players`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MappingSelection {
+    pub virtual_gamepad: Option<usize>,
+    pub gamepad: Option<usize>,
+    pub player: Option<usize>,
+}
+
+impl fmt::Display for MappingSelection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.virtual_gamepad, self.gamepad) {
+            (Some(virtual_gamepad), _) => write!(f, "virtual gamepad {}", virtual_gamepad + 1)?,
+            (None, Some(gamepad)) => write!(f, "gamepad mapping {}", gamepad + 1)?,
+            (None, None) => match self.player {
+                Some(player) => return write!(f, "player {} mapping", player + 1),
+                None => return write!(f, "default mapping"),
+            },
+        }
+        match self.player {
+            Some(player) => write!(f, " as player {}", player + 1),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Identifies gamepads, every field set needs to match
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct GamepadSelector {
+    /// UUID as reported by gilrs, the same used by SDL mappings
+    pub uuid: Option<String>,
+    /// Case insensitive substring of the gamepad name
+    pub name: Option<String>,
+    /// Index of the gamepad, in order of connection
+    pub index: Option<usize>,
+}
+
+impl<A: DeserializeOwned> MappingConfiguration<A> {
+    /// Reads a configuration file in the format told by its extension,
+    /// merged over the files it `extends` and `include`s
+    pub fn load(path: &str) -> Result<Self, MapperError> {
+        MappingConfiguration::load_as(path, ConfigurationFormat::from_path(path))
+    }
+
+    /// Reads a configuration file written in `format`
+    pub fn load_as(path: &str, format: ConfigurationFormat) -> Result<Self, MapperError> {
+        let files = ConfigurationFiles::read(path, format)?;
+        let aliases = files.aliases();
+        serde_yaml::from_value(files.merged()?).map_err(|error| {
+            // serde can't tell which variant of an untagged action was meant,
+            // nor where a merged value comes from, the check can
+            for file in &files.files {
+                let diagnostic = check_with_aliases::<A>(&file.content, file.format, &aliases)
+                    .into_iter()
+                    .find(|diagnostic| diagnostic.severity == Severity::Error);
+                if let Some(diagnostic) = diagnostic {
+                    return MapperError::Parse {
+                        path: Some(file.path.clone()),
+                        line: Some(diagnostic.line),
+                        column: Some(diagnostic.column),
+                        message: diagnostic.message,
+                    };
+                }
+            }
+            MapperError::Parse {
+                path: Some(path.to_string()),
+                line: None,
+                column: None,
+                message: without_location(error.to_string()),
+            }
+        })
+    }
+}
+
+// without bindings nor settings, derived it would need `A: Default`
+impl<A> Default for MappingConfiguration<A> {
+    fn default() -> Self {
+        MappingConfiguration {
+            description: None,
+            mapping: Mapping::default(),
+            gamepads: Vec::new(),
+            virtual_gamepads: Vec::new(),
+            players: Vec::new(),
+            player_combo: Vec::new(),
+            dpad_axis_as_buttons: None,
+            axis_sensitivity: None,
+            mouse_speed: None,
+            scroll_speed: None,
+            controller_mappings: Vec::new(),
+            calibration_file: None,
+            low_battery_threshold: None,
+            battery_poll_interval: None,
+        }
+    }
+}
+
+impl<A> Default for Mapping<A> {
+    fn default() -> Self {
+        Mapping {
+            buttons: FxHashMap::default(),
+            axis: FxHashMap::default(),
+            analog: FxHashMap::default(),
+        }
+    }
+}
+
+impl<A> MappingConfiguration<A> {
+    /// `calibration_file`, or `joystick-mapper.calibration` in the
+    /// configuration directory by default
+    pub fn calibration_path(&self) -> String {
+        if let Some(calibration_file) = &self.calibration_file {
+            return calibration_file.clone();
+        }
+        let calibration_file = Path::new("joystick-mapper.calibration");
+        match configuration_directory("joystick-mapper") {
+            Some(directory) => directory.join(calibration_file),
+            None => calibration_file.to_path_buf(),
+        }
+        .to_string_lossy()
+        .into_owned()
+    }
+
+    /// `controller_mappings` as a single SDL mappings string, reading the listed files
+    pub fn sdl_mappings(&self) -> Result<String, MapperError> {
+        let mut sdl_mappings = String::new();
+        for controller_mapping in &self.controller_mappings {
+            // SDL mapping lines are comma separated, anything else is a file
+            if controller_mapping.contains(',') {
+                sdl_mappings.push_str(controller_mapping);
+            } else {
+                let content = fs::read_to_string(controller_mapping)
+                    .map_err(|error| MapperError::Io(controller_mapping.clone(), error))?;
+                sdl_mappings.push_str(&content);
+            }
+            sdl_mappings.push('\n');
+        }
+        Ok(sdl_mappings)
+    }
+
+    /// Index in `gamepads` of the mapping selecting the gamepad, if any
+    pub fn gamepad_mapping_index(&self, gamepad: &GamepadInfo) -> Option<usize> {
+        self.gamepads
+            .iter()
+            .position(|gamepad_mapping| gamepad_mapping.selector.matches(gamepad))
+    }
+
+    /// Indexes in `virtual_gamepads` and in its parts of the part matching the gamepad, if any
+    pub fn virtual_gamepad_part(&self, gamepad: &GamepadInfo) -> Option<(usize, usize)> {
+        self.virtual_gamepads
+            .iter()
+            .enumerate()
+            .find_map(|(index, virtual_gamepad)| {
+                virtual_gamepad
+                    .parts
+                    .iter()
+                    .position(|part| part.selector.matches(gamepad))
+                    .map(|part| (index, part))
+            })
+    }
+
+    /// Mapping of a gamepad: the one of the virtual gamepad it's part of, its
+    /// own if selected in `gamepads`, the one of its player or the default one
+    pub fn mapping(&self, selection: MappingSelection) -> &Mapping<A> {
+        let virtual_gamepad_mapping = selection
+            .virtual_gamepad
+            .and_then(|index| self.virtual_gamepads.get(index))
+            .map(|virtual_gamepad| &virtual_gamepad.mapping);
+        let gamepad_mapping = selection
+            .gamepad
+            .and_then(|index| self.gamepads.get(index))
+            .map(|gamepad_mapping| &gamepad_mapping.mapping);
+        let player_mapping = selection.player.and_then(|player| self.players.get(player));
+        virtual_gamepad_mapping
+            .or(gamepad_mapping)
+            .or(player_mapping)
+            .unwrap_or(&self.mapping)
+    }
+}
+
+impl<A> Mapping<A> {
+    pub fn button_binding(&self, button: Button, code: Option<u32>) -> Option<&Binding<A>> {
+        Input::lookup(&self.buttons, button, code)
+    }
+
+    pub fn axis_binding(&self, axis: Axis, code: Option<u32>) -> Option<&[A; 2]> {
+        Input::lookup(&self.axis, axis, code)
+    }
+
+    pub fn analog_binding(&self, button: Button, code: Option<u32>) -> Option<&AnalogBinding<A>> {
+        Input::lookup(&self.analog, button, code)
+    }
+}
+
+impl VirtualGamepadPart {
+    pub fn provides_button(&self, button: Button, code: Option<u32>) -> bool {
+        self.buttons.is_empty() || self.buttons.iter().any(|input| input.matches(button, code))
+    }
+
+    pub fn provides_axis(&self, axis: Axis, code: Option<u32>) -> bool {
+        self.axis.is_empty() || self.axis.iter().any(|input| input.matches(axis, code))
+    }
+}
+
+impl<T: Eq + Hash> Input<T> {
+    pub fn matches(&self, named: T, code: Option<u32>) -> bool {
+        match self {
+            Input::Named(input) => *input == named,
+            Input::Code(input) => Some(*input) == code,
+        }
+    }
+
+    // bindings by code win over the ones by name, which can be shared by unknown inputs
+    fn lookup<V>(bindings: &FxHashMap<Input<T>, V>, named: T, code: Option<u32>) -> Option<&V> {
+        code.and_then(|code| bindings.get(&Input::Code(code)))
+            .or_else(|| bindings.get(&Input::Named(named)))
+    }
+}
+
+impl<T: Serialize> Serialize for Input<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Input::Named(input) => input.serialize(serializer),
+            Input::Code(code) => serializer.serialize_str(&format!("code:{}", code)),
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Input<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let input = String::deserialize(deserializer)?;
+        match input.strip_prefix("code:") {
+            Some(code) => code
+                .trim()
+                .parse()
+                .map(Input::Code)
+                .map_err(|_| de::Error::custom(format!("invalid code `{}`", code))),
+            None => {
+                let deserializer: de::value::StringDeserializer<D::Error> =
+                    input.into_deserializer();
+                T::deserialize(deserializer).map(Input::Named)
+            }
+        }
+    }
+}
+
+impl GamepadSelector {
+    pub fn matches(&self, gamepad: &GamepadInfo) -> bool {
+        if let Some(uuid) = &self.uuid {
+            if uuid.replace('-', "").to_lowercase() != gamepad.uuid {
+                return false;
+            }
+        }
+        if let Some(name) = &self.name {
+            if !gamepad.name.to_lowercase().contains(&name.to_lowercase()) {
+                return false;
+            }
+        }
+        if let Some(index) = self.index {
+            let gamepad_index: usize = gamepad.id.into();
+            if index != gamepad_index {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+pub fn gamepad_uuid(gamepad: &Gamepad) -> String {
+    gamepad
+        .uuid()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Binding<A> {
+    Action(A),
+    Actions(Vec<A>),
+    Detailed(DetailedBinding<A>),
+}
+
+/// Actions in `action` are held down while the button is, `on_press` and
+/// `on_release` actions are tapped when the button is pressed and released
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(bound(deserialize = "A: Deserialize<'de>"))]
+pub struct DetailedBinding<A> {
+    #[serde(default = "Vec::new", deserialize_with = "one_or_many")]
+    pub action: Vec<A>,
+    #[serde(default = "Vec::new", deserialize_with = "one_or_many")]
+    pub on_press: Vec<A>,
+    #[serde(default = "Vec::new", deserialize_with = "one_or_many")]
+    pub on_release: Vec<A>,
+    pub repeat: Option<Repeat>,
+}
+
+fn one_or_many<'de, D, A>(deserializer: D) -> Result<Vec<A>, D::Error>
+where
+    D: Deserializer<'de>,
+    A: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<A> {
+        One(A),
+        Many(Vec<A>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(action) => vec![action],
+        OneOrMany::Many(actions) => actions,
+    })
+}
+
+/// Typematic repeat of a held button: after `delay` milliseconds the action
+/// is tapped `rate` times per second until the button is released
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Repeat {
+    #[serde(default = "Repeat::default_delay")]
+    pub delay: u64,
+    /// Between `Repeat::MIN_RATE` and `Repeat::MAX_RATE`
+    #[serde(default = "Repeat::default_rate", deserialize_with = "repeat_rate")]
+    pub rate: f32,
+}
+
+impl Repeat {
+    pub const MIN_RATE: f32 = 0.1;
+    pub const MAX_RATE: f32 = 1000.0;
+
+    fn default_delay() -> u64 {
+        500
+    }
+
+    fn default_rate() -> f32 {
+        20.0
+    }
+
+    // rates out of range are rejected when loading, this only keeps the
+    // ones set by code from stalling the loop or overflowing
+    pub(crate) fn interval(&self) -> Duration {
+        let rate = if self.rate.is_nan() {
+            Repeat::default_rate()
+        } else {
+            self.rate.clamp(Repeat::MIN_RATE, Repeat::MAX_RATE)
+        };
+        Duration::from_secs_f32(1.0 / rate)
+    }
+}
+
+fn repeat_rate<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let rate = f32::deserialize(deserializer)?;
+    // NaN isn't in the range either
+    if (Repeat::MIN_RATE..=Repeat::MAX_RATE).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err(de::Error::custom(format!(
+            "invalid repeat rate `{}`, expected between {} and {} times per second",
+            rate,
+            Repeat::MIN_RATE,
+            Repeat::MAX_RATE
+        )))
+    }
+}
+
+impl<A> Binding<A> {
+    pub fn actions(&self) -> &[A] {
+        match self {
+            Binding::Action(action) => std::slice::from_ref(action),
+            Binding::Actions(actions) => actions,
+            Binding::Detailed(binding) => &binding.action,
+        }
+    }
+
+    pub fn on_press(&self) -> &[A] {
+        match self {
+            Binding::Detailed(binding) => &binding.on_press,
+            _ => &[],
+        }
+    }
+
+    pub fn on_release(&self) -> &[A] {
+        match self {
+            Binding::Detailed(binding) => &binding.on_release,
+            _ => &[],
+        }
+    }
+
+    pub fn repeat(&self) -> Option<Repeat> {
+        match self {
+            Binding::Detailed(binding) => binding.repeat,
+            _ => None,
+        }
+    }
+}
+
+/// Binding on the analog value of a button, like the pull of a trigger
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum AnalogBinding<A> {
+    /// Actions pressed when the value crosses each stage threshold
+    Stages(Vec<AnalogStage<A>>),
+    /// Action receiving the raw value, like a mouse or scroll axis
+    Axis(A),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AnalogStage<A> {
+    pub threshold: f32,
+    /// Value under which the stage is released, defaults to `threshold`
+    pub release_threshold: Option<f32>,
+    pub action: A,
+}
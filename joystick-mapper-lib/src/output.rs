@@ -0,0 +1,119 @@
+use crate::error::MapperError;
+
+use enigo::{Enigo, Key, KeyboardControllable, MouseButton, MouseControllable};
+use std::io::Write;
+
+/// Receives the keyboard and mouse events produced by the actions
+pub trait OutputBackend {
+    fn key_down(&mut self, key: Key) -> Result<(), MapperError>;
+    fn key_up(&mut self, key: Key) -> Result<(), MapperError>;
+    fn mouse_down(&mut self, mouse_button: MouseButton) -> Result<(), MapperError>;
+    fn mouse_up(&mut self, mouse_button: MouseButton) -> Result<(), MapperError>;
+    fn mouse_move_relative(&mut self, x: i32, y: i32) -> Result<(), MapperError>;
+    fn mouse_move_to(&mut self, x: i32, y: i32) -> Result<(), MapperError>;
+    fn mouse_scroll_x(&mut self, length: i32) -> Result<(), MapperError>;
+    fn mouse_scroll_y(&mut self, length: i32) -> Result<(), MapperError>;
+
+    fn mouse_click(&mut self, mouse_button: MouseButton) -> Result<(), MapperError> {
+        self.mouse_down(mouse_button)?;
+        self.mouse_up(mouse_button)
+    }
+}
+
+/// Sends the events to the system keyboard and mouse, which can't fail with enigo 0.0.14
+impl OutputBackend for Enigo {
+    fn key_down(&mut self, key: Key) -> Result<(), MapperError> {
+        KeyboardControllable::key_down(self, key);
+        Ok(())
+    }
+
+    fn key_up(&mut self, key: Key) -> Result<(), MapperError> {
+        KeyboardControllable::key_up(self, key);
+        Ok(())
+    }
+
+    fn mouse_down(&mut self, mouse_button: MouseButton) -> Result<(), MapperError> {
+        MouseControllable::mouse_down(self, mouse_button);
+        Ok(())
+    }
+
+    fn mouse_up(&mut self, mouse_button: MouseButton) -> Result<(), MapperError> {
+        MouseControllable::mouse_up(self, mouse_button);
+        Ok(())
+    }
+
+    fn mouse_click(&mut self, mouse_button: MouseButton) -> Result<(), MapperError> {
+        MouseControllable::mouse_click(self, mouse_button);
+        Ok(())
+    }
+
+    fn mouse_move_relative(&mut self, x: i32, y: i32) -> Result<(), MapperError> {
+        MouseControllable::mouse_move_relative(self, x, y);
+        Ok(())
+    }
+
+    fn mouse_move_to(&mut self, x: i32, y: i32) -> Result<(), MapperError> {
+        MouseControllable::mouse_move_to(self, x, y);
+        Ok(())
+    }
+
+    fn mouse_scroll_x(&mut self, length: i32) -> Result<(), MapperError> {
+        MouseControllable::mouse_scroll_x(self, length);
+        Ok(())
+    }
+
+    fn mouse_scroll_y(&mut self, length: i32) -> Result<(), MapperError> {
+        MouseControllable::mouse_scroll_y(self, length);
+        Ok(())
+    }
+}
+
+/// Writes a line for every event instead of performing it, to compare
+/// the output of a replay with an expected one
+pub struct LogOutput<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> LogOutput<W> {
+    pub fn new(writer: W) -> Self {
+        LogOutput { writer }
+    }
+
+    fn log(&mut self, line: std::fmt::Arguments) -> Result<(), MapperError> {
+        writeln!(self.writer, "{}", line).map_err(|error| MapperError::Output(error.to_string()))
+    }
+}
+
+impl<W: Write> OutputBackend for LogOutput<W> {
+    fn key_down(&mut self, key: Key) -> Result<(), MapperError> {
+        self.log(format_args!("key_down {:?}", key))
+    }
+
+    fn key_up(&mut self, key: Key) -> Result<(), MapperError> {
+        self.log(format_args!("key_up {:?}", key))
+    }
+
+    fn mouse_down(&mut self, mouse_button: MouseButton) -> Result<(), MapperError> {
+        self.log(format_args!("mouse_down {:?}", mouse_button))
+    }
+
+    fn mouse_up(&mut self, mouse_button: MouseButton) -> Result<(), MapperError> {
+        self.log(format_args!("mouse_up {:?}", mouse_button))
+    }
+
+    fn mouse_move_relative(&mut self, x: i32, y: i32) -> Result<(), MapperError> {
+        self.log(format_args!("mouse_move_relative {} {}", x, y))
+    }
+
+    fn mouse_move_to(&mut self, x: i32, y: i32) -> Result<(), MapperError> {
+        self.log(format_args!("mouse_move_to {} {}", x, y))
+    }
+
+    fn mouse_scroll_x(&mut self, length: i32) -> Result<(), MapperError> {
+        self.log(format_args!("mouse_scroll_x {}", length))
+    }
+
+    fn mouse_scroll_y(&mut self, length: i32) -> Result<(), MapperError> {
+        self.log(format_args!("mouse_scroll_y {}", length))
+    }
+}
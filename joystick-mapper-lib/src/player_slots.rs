@@ -0,0 +1,139 @@
+use gilrs::GamepadId;
+
+#[derive(Default)]
+struct PlayerSlot {
+    gamepad: Option<GamepadId>,
+    last_gamepad: Option<GamepadId>,
+    last_uuid: Option<String>,
+}
+
+/// Assigns gamepads to players, a gamepad reconnecting gets its previous slot back
+pub(crate) struct PlayerSlots {
+    slots: Vec<PlayerSlot>,
+}
+
+impl PlayerSlots {
+    pub fn new(players: usize) -> Self {
+        let mut slots = Vec::new();
+        slots.resize_with(players, PlayerSlot::default);
+        PlayerSlots { slots }
+    }
+
+    pub fn player(&self, id: GamepadId) -> Option<usize> {
+        self.slots.iter().position(|slot| slot.gamepad == Some(id))
+    }
+
+    pub fn assign(&mut self, id: GamepadId, uuid: &str) -> Option<usize> {
+        if let Some(player) = self.player(id) {
+            return Some(player);
+        }
+        let free = |slot: &PlayerSlot| slot.gamepad.is_none();
+        let player = self
+            .slots
+            .iter()
+            .position(|slot| free(slot) && slot.last_gamepad == Some(id))
+            .or_else(|| {
+                self.slots
+                    .iter()
+                    .position(|slot| free(slot) && slot.last_uuid.as_deref() == Some(uuid))
+            })
+            .or_else(|| {
+                self.slots
+                    .iter()
+                    .position(|slot| free(slot) && slot.last_gamepad.is_none())
+            })
+            .or_else(|| self.slots.iter().position(free))?;
+        let slot = &mut self.slots[player];
+        slot.gamepad = Some(id);
+        slot.last_gamepad = Some(id);
+        slot.last_uuid = Some(uuid.to_string());
+        Some(player)
+    }
+
+    pub fn disconnect(&mut self, id: GamepadId) {
+        if let Some(player) = self.player(id) {
+            self.slots[player].gamepad = None;
+        }
+    }
+
+    /// Moves the gamepad to the next player, swapping places with the gamepad
+    /// already there, returns the two players involved
+    pub fn next(&mut self, id: GamepadId) -> Option<(usize, usize)> {
+        let player = self.player(id)?;
+        let next_player = (player + 1) % self.slots.len();
+        if next_player == player {
+            return None;
+        }
+        self.slots.swap(player, next_player);
+        Some((player, next_player))
+    }
+
+    pub fn gamepad(&self, player: usize) -> Option<GamepadId> {
+        self.slots.get(player).and_then(|slot| slot.gamepad)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(index: usize) -> GamepadId {
+        serde_json::from_value(index.into()).unwrap()
+    }
+
+    #[test]
+    fn gamepads_take_the_first_free_player() {
+        let mut slots = PlayerSlots::new(2);
+        assert_eq!(slots.assign(id(0), "a"), Some(0));
+        assert_eq!(slots.assign(id(1), "b"), Some(1));
+        assert_eq!(slots.assign(id(2), "c"), None);
+        assert_eq!(slots.assign(id(0), "a"), Some(0));
+        assert_eq!(slots.gamepad(1), Some(id(1)));
+    }
+
+    #[test]
+    fn reconnecting_gamepads_get_their_player_back() {
+        let mut slots = PlayerSlots::new(3);
+        slots.assign(id(0), "a");
+        slots.assign(id(1), "b");
+        slots.disconnect(id(0));
+        assert_eq!(slots.player(id(0)), None);
+        // a new gamepad takes a player nobody had before
+        assert_eq!(slots.assign(id(2), "c"), Some(2));
+        assert_eq!(slots.assign(id(0), "a"), Some(0));
+    }
+
+    #[test]
+    fn gamepads_with_a_new_id_are_known_by_uuid() {
+        let mut slots = PlayerSlots::new(2);
+        slots.assign(id(0), "a");
+        slots.assign(id(1), "b");
+        slots.disconnect(id(0));
+        slots.disconnect(id(1));
+        assert_eq!(slots.assign(id(5), "b"), Some(1));
+        assert_eq!(slots.assign(id(4), "c"), Some(0));
+    }
+
+    #[test]
+    fn next_swaps_with_the_next_player() {
+        let mut slots = PlayerSlots::new(3);
+        slots.assign(id(0), "a");
+        slots.assign(id(1), "b");
+        assert_eq!(slots.next(id(0)), Some((0, 1)));
+        assert_eq!(slots.player(id(0)), Some(1));
+        assert_eq!(slots.player(id(1)), Some(0));
+        assert_eq!(slots.next(id(0)), Some((1, 2)));
+        assert_eq!(slots.gamepad(1), None);
+        assert_eq!(slots.next(id(0)), Some((2, 0)));
+        assert_eq!(slots.player(id(0)), Some(0));
+        assert_eq!(slots.player(id(1)), Some(2));
+    }
+
+    #[test]
+    fn next_needs_another_player() {
+        let mut slots = PlayerSlots::new(1);
+        slots.assign(id(0), "a");
+        assert_eq!(slots.next(id(0)), None);
+        assert_eq!(slots.next(id(1)), None);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::configuration_format::ConfigurationFormat;
+use crate::error::MapperError;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// extensions of configuration files, in order of preference
+const EXTENSIONS: &[&str] = &["yaml", "yml", "toml", "json", "conf"];
+
+/// Configuration of the `profiles` directory
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub path: String,
+    pub description: Option<String>,
+}
+
+/// `$XDG_CONFIG_HOME/name`, or `~/.config/name` when it isn't set
+pub fn configuration_directory(name: &str) -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(directory) if !directory.is_empty() => PathBuf::from(directory),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join(name))
+}
+
+/// The `config` file of a directory, in any format
+pub fn default_configuration(directory: &Path) -> Option<String> {
+    find_configuration(directory, "config")
+}
+
+/// The file of the profile called `name`, like `profiles/minecraft.yaml`
+pub fn find_profile(directory: &Path, name: &str) -> Result<String, MapperError> {
+    let profiles = directory.join("profiles");
+    find_configuration(&profiles, name).ok_or_else(|| {
+        MapperError::Usage(format!(
+            "No profile `{}` in {}",
+            name,
+            profiles.to_string_lossy()
+        ))
+    })
+}
+
+/// Profiles of a directory by name, with the `description` they give.
+/// Profiles that can't be read are listed without one
+pub fn list_profiles(directory: &Path) -> Vec<Profile> {
+    let profiles = directory.join("profiles");
+    let entries = match fs::read_dir(&profiles) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| is_configuration(path))
+        .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+        .into_iter()
+        .filter_map(|name| {
+            // the same name in several formats is the one `find_profile` picks
+            let path = find_configuration(&profiles, &name)?;
+            let description = description(&path);
+            Some(Profile {
+                name,
+                path,
+                description,
+            })
+        })
+        .collect()
+}
+
+fn find_configuration(directory: &Path, name: &str) -> Option<String> {
+    EXTENSIONS
+        .iter()
+        .map(|extension| directory.join(format!("{}.{}", name, extension)))
+        .find(|path| path.is_file())
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+fn is_configuration(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy());
+    path.is_file()
+        && matches!(extension, Some(extension) if EXTENSIONS.contains(&extension.as_ref()))
+}
+
+// only the file's own description, not one it extends
+fn description(path: &str) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    let configuration = ConfigurationFormat::from_path(path)
+        .parse(path, &content)
+        .ok()?;
+    configuration.get("description")?.as_str().map(String::from)
+}
@@ -0,0 +1,206 @@
+use crate::error::MapperError;
+use crate::input_event::InputEvent;
+use crate::script::Script;
+
+use fxhash::FxHashSet;
+use gilrs::GamepadId;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Gamepad event with the milliseconds elapsed since the recording started,
+/// one per line of a recording
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordedEvent {
+    pub time: u64,
+    pub id: GamepadId,
+    pub event: InputEvent,
+    /// Identity of the gamepad, on its first event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gamepad: Option<RecordedGamepad>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordedGamepad {
+    pub name: String,
+    pub uuid: String,
+    /// Whether the gamepad has D-pad buttons besides the D-pad axis
+    #[serde(default)]
+    pub dpad_buttons: bool,
+}
+
+/// Writes gamepad events as JSON lines
+pub struct Recorder<W: Write> {
+    writer: W,
+    start: SystemTime,
+    gamepads: FxHashSet<GamepadId>,
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(writer: W) -> Self {
+        Recorder {
+            writer,
+            start: SystemTime::now(),
+            gamepads: FxHashSet::default(),
+        }
+    }
+
+    /// Whether the gamepad identity was already recorded
+    pub fn knows(&self, id: GamepadId) -> bool {
+        self.gamepads.contains(&id)
+    }
+
+    /// Writes an event that happened at `time`, as told by gilrs
+    pub fn record(
+        &mut self,
+        id: GamepadId,
+        event: InputEvent,
+        time: SystemTime,
+        gamepad: Option<RecordedGamepad>,
+    ) -> Result<(), MapperError> {
+        if gamepad.is_some() {
+            self.gamepads.insert(id);
+        }
+        // events read before the recording started happened at its start
+        let time = time.duration_since(self.start).unwrap_or_default();
+        let recorded_event = RecordedEvent {
+            time: time.as_millis() as u64,
+            id,
+            event,
+            gamepad,
+        };
+        serde_json::to_writer(&mut self.writer, &recorded_event)
+            .map_err(io::Error::from)
+            .and_then(|_| self.writer.write_all(b"\n"))
+            .and_then(|_| self.writer.flush())
+            .map_err(|error| MapperError::Io("recording".to_string(), error))
+    }
+}
+
+/// Recorded events being fed to the mapper, in steps of `TICK` of recording
+/// time so the output doesn't depend on the replay speed
+pub struct Replay {
+    events: Vec<RecordedEvent>,
+    next_event: usize,
+    /// Recording time replayed so far
+    elapsed: Duration,
+    /// Recording time the replay lasts, at least until its last event
+    end: Duration,
+    start: Instant,
+    /// How many times faster than recorded, as fast as possible if 0
+    speed: f32,
+}
+
+impl Replay {
+    pub const TICK: Duration = Duration::from_millis(15);
+
+    pub fn new(events: Vec<RecordedEvent>, speed: f32) -> Self {
+        let end = events.last().map_or(Duration::from_millis(0), |event| {
+            Duration::from_millis(event.time)
+        });
+        Replay {
+            events,
+            next_event: 0,
+            elapsed: Duration::from_millis(0),
+            end,
+            start: Instant::now(),
+            speed,
+        }
+    }
+
+    /// Replays a script until its waits after the last event are over too
+    pub fn script(script: Script, speed: f32) -> Self {
+        let end = script.duration;
+        Replay {
+            end,
+            ..Replay::new(script.events, speed)
+        }
+    }
+
+    pub fn load(path: &str, speed: f32) -> Result<Replay, MapperError> {
+        let io_error = |error| MapperError::Io(path.to_string(), error);
+        let mut events = Vec::new();
+        let file = File::open(path).map_err(io_error)?;
+        for (line_number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(io_error)?;
+            if !line.trim().is_empty() {
+                let event = serde_json::from_str(&line)
+                    .map_err(|error| MapperError::json(path, line_number + 1, error))?;
+                events.push(event);
+            }
+        }
+        Ok(Replay::new(events, speed))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next_event >= self.events.len() && self.elapsed >= self.end
+    }
+
+    /// Moves forward by a tick, returning the events happening in it
+    pub(crate) fn advance(&mut self) -> &[RecordedEvent] {
+        self.elapsed += Replay::TICK;
+        let first_event = self.next_event;
+        let elapsed = self.elapsed.as_millis() as u64;
+        while let Some(event) = self.events.get(self.next_event) {
+            if event.time > elapsed {
+                break;
+            }
+            self.next_event += 1;
+        }
+        &self.events[first_event..self.next_event]
+    }
+
+    /// Instant the replayed events happen at, as the mapper sees it
+    pub(crate) fn now(&self) -> Instant {
+        self.start + self.elapsed
+    }
+
+    pub(crate) fn wait(&self) {
+        if self.speed <= 0_f32 || !self.speed.is_finite() {
+            return;
+        }
+        let due = self.start + self.elapsed.div_f32(self.speed);
+        let now = Instant::now();
+        if due > now {
+            std::thread::sleep(due - now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // times of events recorded at the given offsets from the recording start
+    fn recorded_times(offsets: &[i64]) -> Vec<u64> {
+        let mut recorder = Recorder::new(Vec::new());
+        let id = serde_json::from_str("0").unwrap();
+        for offset in offsets {
+            let offset_duration = Duration::from_millis(offset.unsigned_abs());
+            let time = if *offset < 0 {
+                recorder.start - offset_duration
+            } else {
+                recorder.start + offset_duration
+            };
+            recorder
+                .record(id, InputEvent::Connected, time, None)
+                .unwrap();
+        }
+        String::from_utf8(recorder.writer)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<RecordedEvent>(line).unwrap().time)
+            .collect()
+    }
+
+    #[test]
+    fn events_are_timed_when_they_happened() {
+        assert_eq!(recorded_times(&[250, 40]), vec![250, 40]);
+    }
+
+    #[test]
+    fn events_before_the_recording_start_at_zero() {
+        assert_eq!(recorded_times(&[-1000]), vec![0]);
+    }
+}
@@ -0,0 +1,338 @@
+use crate::action_client::Action;
+use crate::calibration::Calibration;
+use crate::check::{check_with_aliases, Severity};
+use crate::configuration_files::ConfigurationFiles;
+use crate::configuration_format::ConfigurationFormat;
+use crate::error::MapperError;
+use crate::hooks::{GamepadInfo, Hooks};
+use crate::joystick_client::JoystickClient;
+use crate::mapping_configuration::{gamepad_uuid, MappingConfiguration, MappingSelection};
+use crate::output::LogOutput;
+use crate::profiles::{
+    configuration_directory, default_configuration, find_profile, list_profiles,
+};
+use crate::recording::{Recorder, Replay};
+use crate::script::parse_script;
+
+use gilrs::PowerInfo;
+use serde::de::DeserializeOwned;
+use std::fs::File;
+use std::io::{BufWriter, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{env, fs};
+
+/// Settings of a mapper binary built with `run`
+pub struct RunOptions {
+    /// Configuration file used when none is given on the command line and
+    /// the configuration directory has no `config` file
+    pub default_configuration: String,
+    /// Directory holding the default `config` file and the `profiles`,
+    /// `$XDG_CONFIG_HOME/joystick-mapper` by default
+    pub configuration_directory: Option<PathBuf>,
+    /// Told what happens to the gamepads while mapping, `PrintHooks` by default
+    pub hooks: Box<dyn Hooks>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            default_configuration: "joystick-mapper.conf".to_string(),
+            configuration_directory: configuration_directory("joystick-mapper"),
+            hooks: Box::new(PrintHooks),
+        }
+    }
+}
+
+/// Runs a mapper binary with `A` as actions: parses the command line, loads
+/// the configuration and maps the gamepads until stopped by a signal, loading
+/// the configuration again when its file changes.
+///
+/// `mapper [status|calibrate|check] [configuration | --profile name] [--format yaml|json|toml]`,
+/// `mapper record|replay|script file [configuration] [--speed n] [--output log]`
+/// and `mapper profiles list`
+pub fn run<A, S>(state: S, options: RunOptions) -> Result<(), MapperError>
+where
+    A: Action<S> + DeserializeOwned,
+{
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let speed = take_option(&mut args, "--speed")
+        .map(|speed| match speed.parse::<f32>() {
+            // 0 replays as fast as possible
+            Ok(speed) if speed >= 0_f32 && speed.is_finite() => Ok(speed),
+            _ => Err(MapperError::Usage(format!(
+                "Invalid speed `{}`, expected a positive number or 0",
+                speed
+            ))),
+        })
+        .transpose()?
+        .unwrap_or(1.0);
+    let output = take_option(&mut args, "--output");
+    let profile = take_option(&mut args, "--profile");
+    let format = take_option(&mut args, "--format")
+        .map(|format| format.parse::<ConfigurationFormat>())
+        .transpose()?;
+    let mut args = args.into_iter().peekable();
+    let command = match args.peek().map(String::as_str) {
+        Some("status") | Some("calibrate") | Some("check") | Some("record") | Some("replay")
+        | Some("script") | Some("profiles") => args.next(),
+        _ => None,
+    };
+    let mut hooks = options.hooks;
+    let directory = options.configuration_directory.as_deref();
+    if command.as_deref() == Some("profiles") {
+        return match args.next().as_deref() {
+            Some("list") => print_profiles(directory),
+            _ => Err(MapperError::Usage("Expected `profiles list`".to_string())),
+        };
+    }
+    let recording = match command.as_deref() {
+        Some("record") | Some("replay") | Some("script") => {
+            let usage = || MapperError::Usage("Missing recording or script file".to_string());
+            Some(args.next().ok_or_else(usage)?)
+        }
+        _ => None,
+    };
+    let filename = args.next();
+    let named = filename.is_some() || profile.is_some();
+    let filename = match (filename, profile) {
+        (Some(_), Some(_)) => {
+            return Err(MapperError::Usage(
+                "Give either a configuration file or a profile".to_string(),
+            ))
+        }
+        (Some(filename), None) => filename,
+        (None, Some(profile)) => find_profile(directory.ok_or_else(no_directory)?, &profile)?,
+        (None, None) => directory
+            .and_then(default_configuration)
+            .unwrap_or(options.default_configuration),
+    };
+    let format = format.unwrap_or_else(|| ConfigurationFormat::from_path(&filename));
+    if command.as_deref() == Some("check") {
+        return check::<A>(&filename, format);
+    }
+    let conf = match command.as_deref() {
+        // they don't map anything, the configuration only brings its
+        // controller mappings and calibration file when there is one
+        Some("status") | Some("calibrate") | Some("record")
+            if !named && !Path::new(&filename).exists() =>
+        {
+            MappingConfiguration::default()
+        }
+        _ => MappingConfiguration::<A>::load_as(&filename, format)?,
+    };
+    let calibration_path = conf.calibration_path();
+    let mut joystick_client = JoystickClient::new(conf, state)?;
+    match command.as_deref() {
+        Some("status") => {
+            print_status(&joystick_client);
+            return Ok(());
+        }
+        Some("calibrate") => return calibrate(&mut joystick_client, &calibration_path),
+        _ => {}
+    }
+    print_gamepads(&joystick_client);
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_running = running.clone();
+    ctrlc::set_handler(move || handler_running.store(false, Ordering::SeqCst))
+        .map_err(|error| MapperError::Setup(Box::new(error)))?;
+    if let (Some("record"), Some(recording)) = (command.as_deref(), &recording) {
+        let mut recorder = Recorder::new(create_file(recording)?);
+        println!("Recording to {}", recording);
+        return joystick_client.record(&mut recorder, &running);
+    }
+    let replay = match (command.as_deref(), &recording) {
+        (Some("replay"), Some(recording)) => Some(Replay::load(recording, speed)?),
+        (Some("script"), Some(script)) => {
+            let parsed = parse_script(&read_script(script)?).map_err(|error| match error {
+                MapperError::Parse {
+                    line,
+                    column,
+                    message,
+                    ..
+                } => MapperError::Parse {
+                    path: Some(script.clone()),
+                    line,
+                    column,
+                    message,
+                },
+                error => error,
+            })?;
+            Some(Replay::script(parsed, speed))
+        }
+        _ => None,
+    };
+    if let Some(mut replay) = replay {
+        if let Some(output) = output {
+            let log = LogOutput::new(BufWriter::new(create_file(&output)?));
+            joystick_client.set_output(Box::new(log));
+        }
+        return joystick_client.replay(&mut replay, hooks.as_mut(), &running);
+    }
+    joystick_client.watch_configuration(&filename, format);
+    joystick_client.run(hooks.as_mut(), &running)
+}
+
+/// Hooks printing what happens to the gamepads
+pub struct PrintHooks;
+
+impl Hooks for PrintHooks {
+    fn on_connected(&mut self, gamepad: &GamepadInfo) {
+        println!(
+            "New joystick connected: {} (battery: {:?})",
+            gamepad.name, gamepad.power_info
+        );
+    }
+
+    fn on_disconnected(&mut self, gamepad: &GamepadInfo) {
+        println!("Joystick disconnected: {}", gamepad.name);
+    }
+
+    fn on_profile_changed(&mut self, gamepad: &GamepadInfo, selection: MappingSelection) {
+        println!("{} uses the {}", gamepad.name, selection);
+    }
+
+    fn on_low_battery(&mut self, gamepad: &GamepadInfo, charge: u8) {
+        println!("Warning: {} battery is at {}%", gamepad.name, charge);
+    }
+
+    fn on_configuration_reloaded(&mut self, path: &str) {
+        println!("Reloaded {}", path);
+    }
+}
+
+fn print_gamepads<A: Action<S>, S>(client: &JoystickClient<A, S>) {
+    let gamepads = client.gamepads();
+    println!(
+        "Found {} joystick{}",
+        gamepads.len(),
+        if gamepads.len() == 1 { "" } else { "s" }
+    );
+    for (id, gamepad) in gamepads {
+        println!(
+            "{}: {} (uuid: {})",
+            id,
+            gamepad.name(),
+            gamepad_uuid(&gamepad)
+        );
+    }
+}
+
+fn print_status<A: Action<S>, S>(client: &JoystickClient<A, S>) {
+    print_gamepads(client);
+    for gamepad in client.gamepads_info() {
+        let battery = match gamepad.power_info {
+            PowerInfo::Unknown => "unknown".to_string(),
+            PowerInfo::Wired => "wired".to_string(),
+            PowerInfo::Discharging(charge) => format!("{}%", charge),
+            PowerInfo::Charging(charge) => format!("{}%, charging", charge),
+            PowerInfo::Charged => "charged".to_string(),
+        };
+        println!("{}: battery {}", gamepad.id, battery);
+    }
+}
+
+fn print_profiles(directory: Option<&Path>) -> Result<(), MapperError> {
+    let directory = directory.ok_or_else(no_directory)?;
+    let profiles = list_profiles(directory);
+    if profiles.is_empty() {
+        println!("No profiles in {}", directory.join("profiles").display());
+    }
+    let width = profiles
+        .iter()
+        .map(|profile| profile.name.len())
+        .max()
+        .unwrap_or(0);
+    for profile in profiles {
+        match profile.description {
+            Some(description) => {
+                println!("{:width$}  {}", profile.name, description, width = width)
+            }
+            None => println!("{}", profile.name),
+        }
+    }
+    Ok(())
+}
+
+fn no_directory() -> MapperError {
+    MapperError::Usage("No configuration directory, set XDG_CONFIG_HOME or HOME".to_string())
+}
+
+fn calibrate<A: Action<S>, S>(
+    client: &mut JoystickClient<A, S>,
+    path: &str,
+) -> Result<(), MapperError> {
+    print_gamepads(client);
+    println!("Leave the sticks and triggers at rest...");
+    let idle = client.sample_axes(Duration::from_secs(3));
+    println!("Move the sticks in full circles and pull the triggers all the way...");
+    let full = client.sample_axes(Duration::from_secs(8));
+    let mut calibration = Calibration::load(path)?;
+    calibration.update(&idle, &full);
+    calibration.save(path)?;
+    println!("Calibration saved to {}", path);
+    Ok(())
+}
+
+fn check<A: DeserializeOwned>(path: &str, format: ConfigurationFormat) -> Result<(), MapperError> {
+    let files = ConfigurationFiles::read(path, format)?;
+    let aliases = files.aliases();
+    let mut errors = 0;
+    for file in &files.files {
+        for diagnostic in check_with_aliases::<A>(&file.content, file.format, &aliases) {
+            if diagnostic.severity == Severity::Error {
+                errors += 1;
+            }
+            println!("{}:{}", file.path, diagnostic);
+        }
+    }
+    // files fine on their own can still be invalid once merged
+    if errors == 0 {
+        if let Err(error) = MappingConfiguration::<A>::load_as(path, format) {
+            errors += 1;
+            println!("error: {}", error);
+        }
+    }
+    if errors > 0 {
+        return Err(MapperError::Parse {
+            path: Some(path.to_string()),
+            line: None,
+            column: None,
+            message: format!(
+                "{} error{} found",
+                errors,
+                if errors == 1 { "" } else { "s" }
+            ),
+        });
+    }
+    println!("{} is valid", path);
+    Ok(())
+}
+
+// `-` reads the script from the standard input
+fn read_script(path: &str) -> Result<String, MapperError> {
+    let script = if path == "-" {
+        let mut script = String::new();
+        std::io::stdin().read_to_string(&mut script).map(|_| script)
+    } else {
+        fs::read_to_string(path)
+    };
+    script.map_err(|error| MapperError::Io(path.to_string(), error))
+}
+
+fn create_file(path: &str) -> Result<File, MapperError> {
+    File::create(path).map_err(|error| MapperError::Io(path.to_string(), error))
+}
+
+// removes `--name value` from the arguments, returning the value
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == name)?;
+    args.remove(index);
+    if index < args.len() {
+        Some(args.remove(index))
+    } else {
+        None
+    }
+}
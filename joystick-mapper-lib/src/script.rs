@@ -0,0 +1,282 @@
+use crate::error::MapperError;
+use crate::input_event::InputEvent;
+use crate::mapping_configuration::Input;
+use crate::recording::{RecordedEvent, RecordedGamepad};
+
+use fxhash::FxHashSet;
+use gilrs::{Axis, Button, GamepadId};
+use serde::de::value::{Error as ValueError, StrDeserializer};
+use serde::de::IntoDeserializer;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Events of a script, and how long it lasts with the waits after its last event
+#[derive(Debug, Clone)]
+pub struct Script {
+    pub events: Vec<RecordedEvent>,
+    pub duration: Duration,
+}
+
+/// Turns a script of virtual gamepad commands into the events to replay, one
+/// command per line:
+///
+/// - `gamepad 1 Joy-Con (L)` sends the next commands from gamepad 1, named as given
+/// - `press South`, `release South` and `tap South` for buttons
+/// - `button RightTrigger2 0.5` for analog buttons
+/// - `axis LeftStickX 0.8` for axis
+/// - `wait 100ms` or `wait 2s` before the next command
+/// - `disconnect` the gamepad
+///
+/// Buttons and axis can also be given as `code:304`, lines starting with `#` are comments
+pub fn parse_script(script: &str) -> Result<Script, MapperError> {
+    let mut events = Vec::new();
+    let mut time = Duration::from_millis(0);
+    let mut index = 0;
+    let mut names = vec![];
+    let mut connected = FxHashSet::default();
+    for (line_number, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |message: &str| MapperError::Parse {
+            path: None,
+            line: Some(line_number + 1),
+            column: None,
+            message: message.to_string(),
+        };
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let mut argument = || words.next().ok_or_else(|| invalid("missing argument"));
+        let mut gamepad_events = vec![];
+        match command {
+            "gamepad" => {
+                index = argument()?
+                    .parse()
+                    .map_err(|_| invalid("invalid gamepad index"))?;
+                let name = line.splitn(3, char::is_whitespace).nth(2).unwrap_or("");
+                if names.len() <= index {
+                    names.resize(index + 1, None);
+                }
+                if !name.trim().is_empty() {
+                    names[index] = Some(name.trim().to_string());
+                }
+                continue;
+            }
+            "press" | "release" | "tap" => {
+                let (button, code) =
+                    named_input(argument()?).map_err(|_| invalid("unknown button"))?;
+                if command != "release" {
+                    gamepad_events.push(InputEvent::ButtonChanged(button, 1.0, code));
+                    gamepad_events.push(InputEvent::ButtonPressed(button, code));
+                }
+                if command != "press" {
+                    gamepad_events.push(InputEvent::ButtonChanged(button, 0.0, code));
+                    gamepad_events.push(InputEvent::ButtonReleased(button, code));
+                }
+            }
+            "button" | "axis" => {
+                let input = argument()?;
+                let amount: f32 = argument()?.parse().map_err(|_| invalid("invalid amount"))?;
+                gamepad_events.push(if command == "button" {
+                    let (button, code) =
+                        named_input(input).map_err(|_| invalid("unknown button"))?;
+                    InputEvent::ButtonChanged(button, amount, code)
+                } else {
+                    let (axis, code) = named_input(input).map_err(|_| invalid("unknown axis"))?;
+                    InputEvent::AxisChanged(axis, amount, code)
+                });
+            }
+            "wait" => {
+                time = parse_duration(argument()?)
+                    .and_then(|duration| time.checked_add(duration))
+                    .ok_or_else(|| invalid("invalid duration"))?;
+                continue;
+            }
+            "disconnect" => {
+                connected.remove(&index);
+                gamepad_events.push(InputEvent::Disconnected);
+            }
+            _ => return Err(invalid(&format!("unknown command `{}`", command))),
+        }
+        let id = gamepad_id(index).map_err(|_| invalid("invalid gamepad index"))?;
+        if command != "disconnect" && connected.insert(index) {
+            let name = names.get(index).cloned().flatten();
+            events.push(RecordedEvent {
+                time: time.as_millis() as u64,
+                id,
+                event: InputEvent::Connected,
+                gamepad: Some(RecordedGamepad {
+                    name: name.unwrap_or_else(|| format!("Scripted gamepad {}", index)),
+                    uuid: format!("{:032x}", index),
+                    dpad_buttons: false,
+                }),
+            });
+        }
+        for event in gamepad_events {
+            events.push(RecordedEvent {
+                time: time.as_millis() as u64,
+                id,
+                event,
+                gamepad: None,
+            });
+        }
+    }
+    Ok(Script {
+        events,
+        duration: time,
+    })
+}
+
+// inputs given by name have no code, unlike the ones of real gamepads, so
+// that bindings by code don't catch them
+fn named_input<'de, T: Deserialize<'de> + Unknown>(
+    input: &'de str,
+) -> Result<(T, Option<u32>), ValueError> {
+    let deserializer: StrDeserializer<ValueError> = input.into_deserializer();
+    Ok(match Input::<T>::deserialize(deserializer)? {
+        Input::Named(named) => (named, None),
+        Input::Code(code) => (T::UNKNOWN, Some(code)),
+    })
+}
+
+trait Unknown {
+    const UNKNOWN: Self;
+}
+
+impl Unknown for Button {
+    const UNKNOWN: Self = Button::Unknown;
+}
+
+impl Unknown for Axis {
+    const UNKNOWN: Self = Axis::Unknown;
+}
+
+fn parse_duration(duration: &str) -> Option<Duration> {
+    if let Some(millis) = duration.strip_suffix("ms") {
+        millis.parse().ok().map(Duration::from_millis)
+    } else if let Some(seconds) = duration.strip_suffix('s') {
+        // negative, NaN, infinite and overflowing durations are errors
+        seconds
+            .parse()
+            .ok()
+            .and_then(|seconds| Duration::try_from_secs_f32(seconds).ok())
+    } else {
+        None
+    }
+}
+
+// gilrs ids can't be built, but they can be deserialized like in recordings
+fn gamepad_id(index: usize) -> Result<GamepadId, serde_json::Error> {
+    serde_json::from_value(index.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(script: &str) -> Vec<(u64, usize, InputEvent)> {
+        parse_script(script)
+            .unwrap()
+            .events
+            .into_iter()
+            .map(|event| (event.time, event.id.into(), event.event))
+            .collect()
+    }
+
+    fn error_line(script: &str) -> Option<usize> {
+        match parse_script(script) {
+            Err(MapperError::Parse { line, .. }) => line,
+            result => panic!("expected a parse error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn tap_presses_and_releases() {
+        assert_eq!(
+            events("tap South"),
+            vec![
+                (0, 0, InputEvent::Connected),
+                (0, 0, InputEvent::ButtonChanged(Button::South, 1.0, None)),
+                (0, 0, InputEvent::ButtonPressed(Button::South, None)),
+                (0, 0, InputEvent::ButtonChanged(Button::South, 0.0, None)),
+                (0, 0, InputEvent::ButtonReleased(Button::South, None)),
+            ]
+        );
+    }
+
+    #[test]
+    fn waits_add_up() {
+        let script = "press South\nwait 100ms\nwait 1.5s\n# comment\n\nrelease South";
+        let times: Vec<u64> = events(script).iter().map(|(time, _, _)| *time).collect();
+        assert_eq!(times, vec![0, 0, 0, 1600, 1600]);
+    }
+
+    #[test]
+    fn gamepads_connect_on_their_first_command() {
+        let script = "gamepad 1 Joy-Con (L)\naxis LeftStickX 0.5\ngamepad 0\nbutton code:704 0.25";
+        let parsed = parse_script(script).unwrap().events;
+        let gamepad = parsed[0].gamepad.as_ref().unwrap();
+        assert_eq!(gamepad.name, "Joy-Con (L)");
+        assert_eq!(gamepad.uuid, format!("{:032x}", 1));
+        assert_eq!(
+            events(script),
+            vec![
+                (0, 1, InputEvent::Connected),
+                (0, 1, InputEvent::AxisChanged(Axis::LeftStickX, 0.5, None)),
+                (0, 0, InputEvent::Connected),
+                (
+                    0,
+                    0,
+                    InputEvent::ButtonChanged(Button::Unknown, 0.25, Some(704))
+                ),
+            ]
+        );
+        assert_eq!(
+            parsed[2].gamepad.as_ref().unwrap().name,
+            "Scripted gamepad 0"
+        );
+    }
+
+    #[test]
+    fn gamepads_connect_again_after_disconnecting() {
+        let connections = events("tap South\ndisconnect\ntap South")
+            .into_iter()
+            .filter(|(_, _, event)| *event == InputEvent::Connected)
+            .count();
+        assert_eq!(connections, 2);
+    }
+
+    #[test]
+    fn errors_tell_the_line() {
+        assert_eq!(error_line("tap South\n\njump South"), Some(3));
+        assert_eq!(error_line("tap Jump"), Some(1));
+        assert_eq!(error_line("wait 2 minutes"), Some(1));
+        assert_eq!(error_line("axis LeftStickX"), Some(1));
+        assert_eq!(error_line("gamepad one"), Some(1));
+    }
+
+    #[test]
+    fn durations_are_finite_and_not_negative() {
+        for duration in &[
+            "-1s", "NaNs", "infs", "-infs", "1e30s", "-5ms", "1.5ms", "s",
+        ] {
+            assert_eq!(
+                error_line(&format!("wait {}", duration)),
+                Some(1),
+                "{}",
+                duration
+            );
+        }
+        assert_eq!(error_line("wait 1e19s\nwait 1e19s"), Some(2));
+        assert_eq!(parse_duration("0s"), Some(Duration::from_secs(0)));
+        assert_eq!(parse_duration("0.25s"), Some(Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn trailing_waits_make_the_script_last() {
+        let script = parse_script("press South\nwait 1s\nwait 250ms").unwrap();
+        assert_eq!(script.events.last().unwrap().time, 0);
+        assert_eq!(script.duration, Duration::from_millis(1250));
+    }
+}
@@ -0,0 +1,136 @@
+use joystick_mapper_lib::{
+    Action, ActionClient, GamepadInfo, Hooks, InputState, JoystickClient, Key, LogOutput,
+    MapperError, MappingConfiguration, MouseAction, MouseButton, Replay,
+};
+
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+
+/// The actions of the mapper binaries
+#[derive(Clone, Copy, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum KeyMouseAction {
+    Key(Key),
+    Layout(char),
+    Mouse(MouseAction),
+    Vent(VentAction),
+}
+
+/// Stands in for the vent actions of `joystick-mapper-among-us`, which look
+/// for the vents on the screen, by clicking a spot in their direction
+// named like in the configurations
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Copy, Deserialize, Debug)]
+pub enum VentAction {
+    VentUp,
+    VentDown,
+    VentRight,
+    VentLeft,
+}
+
+impl Action<()> for KeyMouseAction {
+    fn perform_action(
+        &self,
+        client: &mut ActionClient<()>,
+        input_state: InputState,
+        amount: Option<f32>,
+    ) -> Result<(), MapperError> {
+        match self {
+            KeyMouseAction::Key(key) => key.perform_action(client, input_state, amount),
+            KeyMouseAction::Layout(c) => {
+                Key::Layout(*c).perform_action(client, input_state, amount)
+            }
+            KeyMouseAction::Mouse(mouse) => mouse.perform_action(client, input_state, amount),
+            KeyMouseAction::Vent(vent) => vent.perform_action(client, input_state, amount),
+        }
+    }
+}
+
+impl Action<()> for VentAction {
+    fn perform_action(
+        &self,
+        client: &mut ActionClient<()>,
+        input_state: InputState,
+        _amount: Option<f32>,
+    ) -> Result<(), MapperError> {
+        if input_state == InputState::Up {
+            return Ok(());
+        }
+        let (x, y) = match self {
+            VentAction::VentUp => (960, 100),
+            VentAction::VentDown => (960, 980),
+            VentAction::VentRight => (1820, 540),
+            VentAction::VentLeft => (100, 540),
+        };
+        client.output.mouse_move_to(x, y)?;
+        client.output.mouse_click(MouseButton::Left)
+    }
+}
+
+// log still readable once the client owns its output
+#[derive(Clone, Default)]
+struct SharedLog(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// gamepads connecting and disconnecting go in the log between the actions
+struct LogHooks(SharedLog);
+
+impl Hooks for LogHooks {
+    fn on_connected(&mut self, gamepad: &GamepadInfo) {
+        writeln!(self.0, "connected {}", gamepad.name).unwrap();
+    }
+
+    fn on_disconnected(&mut self, gamepad: &GamepadInfo) {
+        writeln!(self.0, "disconnected {}", gamepad.name).unwrap();
+    }
+
+    fn on_error(&mut self, error: &MapperError) {
+        writeln!(self.0, "error {}", error).unwrap();
+    }
+}
+
+/// Maps the whole replay, returning what was logged. Configurations without
+/// a `calibration_file` get an empty one rather than the one of the user.
+pub fn replay_log<A: Action<()>>(
+    mut configuration: MappingConfiguration<A>,
+    mut replay: Replay,
+) -> String {
+    if configuration.calibration_file.is_none() {
+        let empty = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/common/empty.calibration");
+        configuration.calibration_file = Some(empty.to_string_lossy().into_owned());
+    }
+    let log = SharedLog::default();
+    let mut client = JoystickClient::new(configuration, ()).unwrap();
+    client.set_output(Box::new(LogOutput::new(log.clone())));
+    let mut hooks = LogHooks(log.clone());
+    client
+        .replay(&mut replay, &mut hooks, &AtomicBool::new(true))
+        .unwrap();
+    let output = log.0.borrow().clone();
+    String::from_utf8(output).unwrap()
+}
+
+/// Compares a log with the expected one, `UPDATE_GOLDEN=1` writes it instead
+pub fn assert_golden(path: &Path, log: &str) {
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(path, log).unwrap();
+        return;
+    }
+    let expected =
+        fs::read_to_string(path).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+    assert_eq!(log, expected, "output differs from {}", path.display());
+}
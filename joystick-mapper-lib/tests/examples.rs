@@ -0,0 +1,33 @@
+mod common;
+
+use common::{assert_golden, replay_log, KeyMouseAction};
+use joystick_mapper_lib::{parse_script, MappingConfiguration, Replay};
+
+use std::fs;
+use std::path::Path;
+
+// every example mapping the gamepad of `examples/test.script`, compared with
+// the output expected in `tests/examples`
+#[test]
+fn examples_match_expected_output() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+    let script = fs::read_to_string(root.join("examples/test.script")).unwrap();
+    let mut examples: Vec<_> = fs::read_dir(root.join("examples"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().and_then(|extension| extension.to_str()) == Some("conf"))
+        .collect();
+    examples.sort();
+    assert!(!examples.is_empty());
+    for example in examples {
+        let configuration = MappingConfiguration::<KeyMouseAction>::load(example.to_str().unwrap())
+            .unwrap_or_else(|error| panic!("{}: {}", example.display(), error));
+        let replay = Replay::script(parse_script(&script).unwrap(), 0.0);
+        let log = replay_log(configuration, replay);
+        let name = example.file_stem().unwrap().to_str().unwrap();
+        let expected = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/examples")
+            .join(format!("{}.log", name));
+        assert_golden(&expected, &log);
+    }
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::{assert_golden, replay_log, KeyMouseAction};
+use joystick_mapper_lib::{parse_script, MappingConfiguration, RecordedEvent, Replay};
+
+use std::path::Path;
+
+// recording of a gamepad without D-pad buttons, one event per line
+fn recording(events: &[&str]) -> Replay {
+    let connected = r#"{"time":0,"id":0,"event":"Connected","gamepad":{"name":"Hat","uuid":"0"}}"#;
+    let events = std::iter::once(connected)
+        .chain(events.iter().copied())
+        .map(|event| serde_json::from_str::<RecordedEvent>(event).unwrap())
+        .collect();
+    Replay::new(events, 0.0)
+}
+
+#[test]
+fn replay_matches_expected_actions() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/replay");
+    let configuration = directory.join("configuration.yaml");
+    let configuration =
+        MappingConfiguration::<KeyMouseAction>::load(configuration.to_str().unwrap()).unwrap();
+    let recording = directory.join("recording.jsonl");
+    let replay = Replay::load(recording.to_str().unwrap(), 0.0).unwrap();
+    let log = replay_log(configuration, replay);
+    assert_golden(&directory.join("expected.log"), &log);
+}
+
+#[test]
+fn scripted_names_only_match_bindings_by_name() {
+    let configuration: MappingConfiguration<KeyMouseAction> =
+        serde_yaml::from_str("buttons:\n  South: a\n  code:0: b\n  code:704: c\n").unwrap();
+    let script = parse_script("tap South\ntap code:704").unwrap();
+    let log = replay_log(configuration, Replay::script(script, 0.0));
+    assert_eq!(
+        log,
+        "connected Scripted gamepad 0\n\
+         key_down Layout('a')\nkey_up Layout('a')\n\
+         key_down Layout('c')\nkey_up Layout('c')\n"
+    );
+}
+
+#[test]
+fn dpad_axis_buttons_have_no_code() {
+    let configuration: MappingConfiguration<KeyMouseAction> =
+        serde_yaml::from_str("buttons:\n  DPadUp: w\n  code:17: x\n").unwrap();
+    let replay = recording(&[
+        r#"{"time":0,"id":0,"event":{"AxisChanged":["DPadY",1.0,17]}}"#,
+        r#"{"time":100,"id":0,"event":{"AxisChanged":["DPadY",0.0,17]}}"#,
+    ]);
+    assert_eq!(
+        replay_log(configuration, replay),
+        "connected Hat\nkey_down Layout('w')\nkey_up Layout('w')\n"
+    );
+}
+
+#[test]
+fn dpad_axis_is_left_alone_once_dpad_buttons_come() {
+    let configuration: MappingConfiguration<KeyMouseAction> =
+        serde_yaml::from_str("buttons:\n  DPadUp: w\n").unwrap();
+    let replay = recording(&[
+        r#"{"time":0,"id":0,"event":{"ButtonPressed":["DPadUp",544]}}"#,
+        r#"{"time":0,"id":0,"event":{"AxisChanged":["DPadY",1.0,17]}}"#,
+        r#"{"time":100,"id":0,"event":{"ButtonReleased":["DPadUp",544]}}"#,
+        r#"{"time":100,"id":0,"event":{"AxisChanged":["DPadY",0.0,17]}}"#,
+    ]);
+    assert_eq!(
+        replay_log(configuration, replay),
+        "connected Hat\nkey_down Layout('w')\nkey_up Layout('w')\n"
+    );
+}
+
+#[test]
+fn scripts_last_until_their_last_wait() {
+    let configuration: MappingConfiguration<KeyMouseAction> = serde_yaml::from_str(
+        "buttons:\n  South:\n    action: a\n    repeat:\n      delay: 100\n      rate: 20\n",
+    )
+    .unwrap();
+    let script = parse_script("press South\nwait 200ms").unwrap();
+    let log = replay_log(configuration, Replay::script(script, 0.0));
+    // pressed, repeated twice, then released once the wait is over
+    assert_eq!(log.matches("key_down Layout('a')").count(), 3, "{}", log);
+    assert!(log.ends_with("key_up Layout('a')\n"), "{}", log);
+}